@@ -0,0 +1,263 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use my_lib::{codec::Codec, pool::ModulePool, ModuleBuilder};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{input, options::ModuleOptions};
+
+#[derive(StructOpt)]
+pub(crate) struct BenchOptions {
+    /// The WebAssembly file to benchmark.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+
+    /// The operation to invoke.
+    pub(crate) operation: String,
+
+    /// The path to the input data, or - to read stdin.
+    #[structopt(parse(from_os_str))]
+    pub(crate) input: PathBuf,
+
+    /// How many calls to time, both for latency and throughput.
+    #[structopt(long, default_value = "1000")]
+    pub(crate) iterations: usize,
+
+    /// How many untimed calls to make first.
+    #[structopt(long, default_value = "10")]
+    pub(crate) warmup: usize,
+
+    /// How many times to instantiate the module.
+    #[structopt(long, default_value = "10")]
+    pub(crate) instantiations: usize,
+
+    /// How many threads to spread the throughput run over, one instance each.
+    #[structopt(long, default_value = "4")]
+    pub(crate) threads: usize,
+
+    /// Print the results as JSON, to compare across builds.
+    #[structopt(long)]
+    pub(crate) json: bool,
+
+    #[structopt(flatten)]
+    pub(crate) module: ModuleOptions,
+}
+
+/// Durations are reported in microseconds.
+#[derive(Serialize)]
+struct Report {
+    operation: String,
+    /// Compiling and instantiating the module.
+    instantiation: Summary,
+    /// Loading the module from the compile cache instead, when it's enabled.
+    cached_instantiation: Option<Summary>,
+    latency: Summary,
+    throughput: Throughput,
+    memory: Memory,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    samples: usize,
+    mean_us: f64,
+    min_us: f64,
+    p50_us: f64,
+    p90_us: f64,
+    p99_us: f64,
+    max_us: f64,
+}
+
+#[derive(Serialize)]
+struct Throughput {
+    threads: usize,
+    calls: usize,
+    calls_per_sec: f64,
+}
+
+/// The guest's linear memory before and after the latency run, so growth
+/// across calls shows up as the difference.
+#[derive(Serialize)]
+struct Memory {
+    before_bytes: usize,
+    after_bytes: usize,
+}
+
+pub(crate) fn bench(options: BenchOptions) -> anyhow::Result<()> {
+    anyhow::ensure!(options.iterations > 0, "--iterations must be at least 1");
+    anyhow::ensure!(options.threads > 0, "--threads must be at least 1");
+    let builder = options
+        .module
+        .uncached_builder(&options.file_path)
+        .for_file(&options.file_path)?;
+    let bytes = my_lib::read(&options.file_path)?;
+    let data = input::read(&options.input)?;
+    let payload = options.module.codec.encode(&data)?;
+
+    let instantiation = instantiate(&builder, &bytes, options.instantiations)?;
+    #[cfg(feature = "wasmtime")]
+    let cached_instantiation = match options.module.cache() {
+        Some(cache) => {
+            let builder = builder.clone().cache(cache);
+            // Fill the cache first, so only loads are timed.
+            builder.clone().build(&bytes)?;
+            Some(instantiate(&builder, &bytes, options.instantiations)?)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "wasmtime"))]
+    let cached_instantiation = None;
+
+    let module = builder.clone().build(&bytes)?;
+    module.validate(&options.operation, &data)?;
+    for _ in 0..options.warmup {
+        module.run(&options.operation, &payload)?;
+    }
+    let before_bytes = module.memory_size();
+    let mut samples = Vec::with_capacity(options.iterations);
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        module.run(&options.operation, &payload)?;
+        samples.push(start.elapsed());
+    }
+    let memory = Memory {
+        before_bytes,
+        after_bytes: module.memory_size(),
+    };
+    let latency = summarize(samples);
+    drop(module);
+
+    let throughput = throughput(
+        &options,
+        ModulePool::new(builder, &bytes, options.threads)?,
+        &payload,
+    )?;
+
+    let report = Report {
+        operation: options.operation,
+        instantiation,
+        cached_instantiation,
+        latency,
+        throughput,
+        memory,
+    };
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print(&report);
+    }
+    Ok(())
+}
+
+fn instantiate(builder: &ModuleBuilder, bytes: &[u8], times: usize) -> anyhow::Result<Summary> {
+    let mut samples = Vec::with_capacity(times);
+    for _ in 0..times {
+        let start = Instant::now();
+        let module = builder.clone().build(bytes)?;
+        samples.push(start.elapsed());
+        drop(module);
+    }
+    Ok(summarize(samples))
+}
+
+fn throughput(
+    options: &BenchOptions,
+    pool: ModulePool,
+    payload: &[u8],
+) -> anyhow::Result<Throughput> {
+    let pool = Arc::new(pool);
+    let per_thread = options.iterations.div_ceil(options.threads);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..options.threads)
+        .map(|_| {
+            let pool = pool.clone();
+            let operation = options.operation.clone();
+            let payload = payload.to_vec();
+            thread::spawn(move || {
+                for _ in 0..per_thread {
+                    pool.run(&operation, &payload)?;
+                }
+                Ok::<_, my_lib::error::Error>(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Benchmark thread panicked"))??;
+    }
+    let elapsed = start.elapsed();
+    let calls = per_thread * options.threads;
+    Ok(Throughput {
+        threads: options.threads,
+        calls,
+        calls_per_sec: calls as f64 / elapsed.as_secs_f64(),
+    })
+}
+
+fn summarize(mut samples: Vec<Duration>) -> Summary {
+    if samples.is_empty() {
+        return Summary {
+            samples: 0,
+            mean_us: 0.0,
+            min_us: 0.0,
+            p50_us: 0.0,
+            p90_us: 0.0,
+            p99_us: 0.0,
+            max_us: 0.0,
+        };
+    }
+    samples.sort();
+    let micros = |d: Duration| d.as_secs_f64() * 1_000_000.0;
+    let percentile = |p: f64| {
+        let index = ((samples.len() - 1) as f64 * p).round() as usize;
+        micros(samples[index])
+    };
+    let total: Duration = samples.iter().sum();
+    Summary {
+        samples: samples.len(),
+        mean_us: micros(total) / samples.len() as f64,
+        min_us: micros(samples[0]),
+        p50_us: percentile(0.5),
+        p90_us: percentile(0.9),
+        p99_us: percentile(0.99),
+        max_us: micros(samples[samples.len() - 1]),
+    }
+}
+
+fn print(report: &Report) {
+    println!("{}", report.operation);
+    let summaries = [
+        ("instantiation", Some(&report.instantiation)),
+        ("cached", report.cached_instantiation.as_ref()),
+        ("call", Some(&report.latency)),
+    ];
+    for (name, summary) in summaries {
+        let summary = match summary {
+            Some(summary) => summary,
+            None => continue,
+        };
+        println!(
+            "  {:<14} n={:<6} mean={:.1}us p50={:.1}us p90={:.1}us p99={:.1}us max={:.1}us",
+            name,
+            summary.samples,
+            summary.mean_us,
+            summary.p50_us,
+            summary.p90_us,
+            summary.p99_us,
+            summary.max_us
+        );
+    }
+    println!(
+        "  {:<14} {:.0} calls/s over {} threads",
+        "throughput", report.throughput.calls_per_sec, report.throughput.threads
+    );
+    println!(
+        "  {:<14} {} bytes of guest memory, {} after the calls",
+        "memory", report.memory.before_bytes, report.memory.after_bytes
+    );
+}
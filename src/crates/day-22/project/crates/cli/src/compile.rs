@@ -0,0 +1,27 @@
+use std::{fs, path::PathBuf};
+
+use my_lib::aot;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub(crate) struct CompileOptions {
+    /// The WebAssembly file to compile.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+
+    /// Where to write the compiled module, conventionally a .cwasm file.
+    #[structopt(short, long, parse(from_os_str))]
+    pub(crate) output: PathBuf,
+}
+
+pub(crate) fn compile(options: CompileOptions) -> anyhow::Result<()> {
+    let bytes = fs::read(&options.file_path)?;
+    let compiled = aot::compile(&bytes)?;
+    fs::write(&options.output, compiled)?;
+    info!(
+        "Wrote {} for {}",
+        options.output.display(),
+        aot::ENGINE_VERSION
+    );
+    Ok(())
+}
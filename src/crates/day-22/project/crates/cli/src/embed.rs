@@ -0,0 +1,29 @@
+use std::{fs, path::PathBuf};
+
+use my_lib::introspect;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub(crate) struct EmbedOptions {
+    /// The WebAssembly file to embed the interface in.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+
+    /// The WIDL schema the module was generated from.
+    #[structopt(parse(from_os_str))]
+    pub(crate) schema_path: PathBuf,
+
+    /// Where to write the module with its interface embedded.
+    #[structopt(short, long, parse(from_os_str))]
+    pub(crate) output: PathBuf,
+}
+
+pub(crate) fn embed(options: EmbedOptions) -> anyhow::Result<()> {
+    let bytes = fs::read(&options.file_path)?;
+    let widl = fs::read_to_string(&options.schema_path)?;
+
+    let embedded = introspect::embed_interface(&bytes, &widl)?;
+    fs::write(&options.output, embedded)?;
+    info!("Wrote {}", options.output.display());
+    Ok(())
+}
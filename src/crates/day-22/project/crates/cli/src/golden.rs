@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use my_lib::golden::{self, Outcome};
+use structopt::StructOpt;
+
+use crate::options::ModuleOptions;
+
+#[derive(StructOpt)]
+pub(crate) struct TestOptions {
+    /// The directory holding the wasm files and their fixtures.
+    #[structopt(parse(from_os_str))]
+    pub(crate) dir: PathBuf,
+
+    /// Overwrite the expected outputs with what the modules actually return.
+    #[structopt(long)]
+    pub(crate) bless: bool,
+
+    #[structopt(flatten)]
+    pub(crate) module: ModuleOptions,
+}
+
+pub(crate) fn test(options: TestOptions) -> anyhow::Result<()> {
+    let builder = options.module.builder(&options.dir);
+    let report = golden::run(&builder, &options.dir, options.bless)?;
+    if report.results.is_empty() {
+        anyhow::bail!("No fixtures found in {}", options.dir.display());
+    }
+
+    for (fixture, outcome) in &report.results {
+        match outcome {
+            Outcome::Passed => println!("ok      {}", fixture),
+            Outcome::Blessed => println!("blessed {}", fixture),
+            Outcome::Failed(differences) => {
+                println!("FAILED  {}", fixture);
+                for difference in differences {
+                    println!("        {}", difference);
+                }
+            }
+            Outcome::Errored(e) => {
+                println!("ERROR   {}", fixture);
+                println!("        {}", e);
+            }
+        }
+    }
+
+    let failures = report.failures();
+    println!("\n{} fixtures, {} failed", report.results.len(), failures);
+    if !report.succeeded() {
+        anyhow::bail!("{} of {} fixtures failed", failures, report.results.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use anyhow::Context;
+
+/// Reads a payload from `path`, or from stdin if `path` is `-`.
+///
+/// Files ending in `.yaml`, `.yml` or `.toml` are parsed as such, everything
+/// else is parsed as JSON.
+pub(crate) fn read(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let text = if is_stdin(path) {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?
+    };
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let value = match extension {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text)?,
+        Some("toml") => toml::from_str(&text)?,
+        _ => serde_json::from_str(&text)?,
+    };
+    Ok(value)
+}
+
+/// Parses an inline JSON payload, as passed to `--data`.
+pub(crate) fn parse(data: &str) -> anyhow::Result<serde_json::Value> {
+    Ok(serde_json::from_str(data)?)
+}
+
+/// Opens `path` for reading newline-delimited JSON, or stdin if `path` is `-`.
+pub(crate) fn lines(path: &Path) -> anyhow::Result<Box<dyn BufRead>> {
+    if is_stdin(path) {
+        return Ok(Box::new(BufReader::new(io::stdin())));
+    }
+    let file =
+        fs::File::open(path).with_context(|| format!("Could not read {}", path.display()))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reads_payloads_by_extension() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("wapc-runner-input-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let expected = json!({ "title": "Hello", "tags": ["a"] });
+        for (name, text) in [
+            ("post.json", r#"{"title": "Hello", "tags": ["a"]}"#),
+            ("post.yaml", "title: Hello\ntags: [a]\n"),
+            ("post.yml", "title: Hello\ntags:\n  - a\n"),
+            ("post.toml", "title = \"Hello\"\ntags = [\"a\"]\n"),
+            // Anything else is JSON.
+            ("post.txt", r#"{"title": "Hello", "tags": ["a"]}"#),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, text)?;
+            assert_eq!(read(&path)?, expected, "{}", name);
+        }
+
+        fs::write(dir.join("broken.yaml"), "title: [")?;
+        assert!(read(&dir.join("broken.yaml")).is_err());
+        let missing = read(&dir.join("missing.json")).unwrap_err();
+        assert!(missing.to_string().contains("missing.json"), "{}", missing);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn opens_batches_line_by_line() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("wapc-runner-batch-{}", std::process::id()));
+        fs::write(&path, "{\"id\": 1}\n{\"id\": 2}\n")?;
+        let batch = lines(&path)?.lines().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batch, [r#"{"id": 1}"#, r#"{"id": 2}"#]);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn parses_inline_data() {
+        assert_eq!(parse(r#"{"id": 1}"#).unwrap(), json!({ "id": 1 }));
+        assert!(parse("id: 1").is_err());
+    }
+
+    #[test]
+    fn treats_dash_as_stdin() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("./-")));
+        assert!(!is_stdin(Path::new("post.json")));
+    }
+}
@@ -0,0 +1,35 @@
+use std::{fs, path::PathBuf};
+
+use my_lib::introspect;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub(crate) struct InspectOptions {
+    /// The WebAssembly file to inspect.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+}
+
+pub(crate) fn inspect(options: InspectOptions) -> anyhow::Result<()> {
+    let bytes = fs::read(&options.file_path)?;
+    let info = introspect::inspect(&bytes)?;
+
+    println!("Exports:");
+    for export in &info.exports {
+        println!("  {} ({})", export.name, export.kind);
+    }
+    println!("Imports:");
+    for import in &info.imports {
+        println!("  {}::{} ({})", import.module, import.name, import.kind);
+    }
+    match &info.interface {
+        Some(interface) => {
+            println!("Operations:");
+            for operation in &interface.operations {
+                println!("  {}", operation);
+            }
+        }
+        None => println!("No embedded interface, operations are only known at runtime."),
+    }
+    Ok(())
+}
@@ -1,7 +1,39 @@
-use std::{fs, path::PathBuf};
+mod bench;
+#[cfg(feature = "wasmtime")]
+mod compile;
+mod embed;
+mod golden;
+mod input;
+mod inspect;
+mod metrics;
+mod options;
+mod output;
+mod repl;
+mod serve;
+mod sign;
 
-use my_lib::Module;
-use structopt::{clap::AppSettings, StructOpt};
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use structopt::{
+    clap::{AppSettings, ErrorKind},
+    StructOpt,
+};
+
+use bench::BenchOptions;
+#[cfg(feature = "wasmtime")]
+use compile::CompileOptions;
+use embed::EmbedOptions;
+use golden::TestOptions;
+use inspect::InspectOptions;
+use my_lib::{codec::Codec, Module};
+use options::ModuleOptions;
+use output::{Failure, Kind, OutputFormat, OutputOptions};
+use repl::ReplOptions;
+use serve::ServeOptions;
+use sign::{KeygenOptions, SignOptions};
 
 #[macro_use]
 extern crate log;
@@ -13,52 +45,216 @@ extern crate log;
     global_settings(&[
       AppSettings::ColoredHelp
     ]),
+    // Once a file is given, an operation like `hello` is never a subcommand.
+    settings(&[AppSettings::ArgsNegateSubcommands]),
 )]
 struct CliOptions {
+    #[structopt(subcommand)]
+    pub(crate) command: Option<Command>,
+
     /// The WebAssembly file to load.
     #[structopt(parse(from_os_str))]
-    pub(crate) file_path: PathBuf,
+    pub(crate) file_path: Option<PathBuf>,
 
     /// The operation to invoke in the WASM file.
     #[structopt()]
-    pub(crate) operation: String,
+    pub(crate) operation: Option<String>,
 
-    /// The path to the JSON data to use as input.
+    /// The path to the JSON, YAML or TOML data to use as input, or - to read stdin.
     #[structopt(parse(from_os_str))]
-    pub(crate) json_path: PathBuf,
+    pub(crate) json_path: Option<PathBuf>,
+
+    /// Inline JSON to use as input instead of a file.
+    #[structopt(long, conflicts_with = "json-path")]
+    pub(crate) data: Option<String>,
+
+    /// Treat the input as newline-delimited JSON, invoke the operation once per
+    /// line and write one JSON result per line.
+    #[structopt(long, conflicts_with = "data")]
+    pub(crate) batch: bool,
+
+    /// Run a module compiled with `compile`. Its native code isn't checked, so
+    /// only pass files you compiled yourself.
+    #[cfg(feature = "wasmtime")]
+    #[structopt(long)]
+    pub(crate) precompiled: bool,
+
+    #[structopt(flatten)]
+    pub(crate) module: ModuleOptions,
+
+    #[structopt(flatten)]
+    pub(crate) output: OutputOptions,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List a module's exports, imports, and the operations it declares.
+    Inspect(InspectOptions),
+    /// Embed a WIDL interface in a module so its inputs can be validated.
+    Embed(EmbedOptions),
+    /// Serve a module's operations over HTTP at POST /{operation}, and metrics at GET /metrics.
+    Serve(ServeOptions),
+    /// Load a module and invoke its operations interactively.
+    Repl(ReplOptions),
+    /// Run the golden-file fixtures next to the wasm files in a directory.
+    Test(TestOptions),
+    /// Measure instantiation time, call latency, throughput and memory growth.
+    Bench(BenchOptions),
+    /// Compile a module ahead of time so it loads without compiling again.
+    #[cfg(feature = "wasmtime")]
+    Compile(CompileOptions),
+    /// Generate an ed25519 key pair for signing modules.
+    Keygen(KeygenOptions),
+    /// Sign a module so it can be loaded with --trusted-key.
+    Sign(SignOptions),
 }
 
 fn main() {
     env_logger::init();
     debug!("Initialized logger");
 
-    let options = CliOptions::from_args();
+    let mut options = CliOptions::from_args();
 
-    match run(options) {
-        Ok(output) => {
-            println!("{}", output);
-            info!("Done");
-        }
-        Err(e) => {
-            error!("Module failed to load: {}", e);
+    if let Some(command) = options.command.take() {
+        let result = match command {
+            Command::Inspect(options) => inspect::inspect(options),
+            Command::Embed(options) => embed::embed(options),
+            Command::Serve(options) => serve::serve(options),
+            Command::Repl(options) => repl::repl(options),
+            Command::Test(options) => golden::test(options),
+            Command::Bench(options) => bench::bench(options),
+            #[cfg(feature = "wasmtime")]
+            Command::Compile(options) => compile::compile(options),
+            Command::Keygen(options) => sign::keygen(options),
+            Command::Sign(options) => sign::sign(options),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
             std::process::exit(1);
         }
-    };
+        return;
+    }
+
+    if let Err(failure) = run(options) {
+        eprintln!("{}", failure.to_json());
+        std::process::exit(failure.kind.exit_code());
+    }
+    info!("Done");
 }
 
-fn run(options: CliOptions) -> anyhow::Result<serde_json::Value> {
-    let module = Module::from_file(&options.file_path)?;
+fn run(options: CliOptions) -> Result<(), Failure> {
+    let file_path = required(options.file_path, "FILE_PATH");
+    let operation = required(options.operation, "OPERATION");
+
+    let builder = options.module.builder(&file_path);
+    #[cfg(feature = "wasmtime")]
+    let module = if options.precompiled {
+        builder.from_precompiled(&file_path)
+    } else {
+        builder.from_file(&file_path)
+    };
+    #[cfg(not(feature = "wasmtime"))]
+    let module = builder.from_file(&file_path);
+    let module = module.map_err(|e| Failure::new(Kind::Load, e))?;
     info!("Module loaded");
 
-    let json = fs::read_to_string(options.json_path)?;
-    let data: serde_json::Value = serde_json::from_str(&json)?;
+    if options.batch {
+        let json_path = required(options.json_path, "JSON_PATH");
+        let input = input::lines(&json_path).map_err(|e| Failure::new(Kind::Io, e))?;
+        return batch(&module, &operation, input, &options.output);
+    }
+
+    let data = match options.data {
+        Some(data) => input::parse(&data)?,
+        None => input::read(&required(options.json_path, "JSON_PATH"))?,
+    };
     debug!("Data: {:?}", data);
 
-    let bytes = rmp_serde::to_vec(&data)?;
+    debug!("Running {}", operation);
+    let format = options.output.format();
+    let output = if format == OutputFormat::Raw {
+        module.validate(&operation, &data)?;
+        let payload = options.module.codec.encode(&data)?;
+        module.run(&operation, &payload)?
+    } else {
+        let result: serde_json::Value = module.invoke(&operation, &data)?;
+        format.render(&result)?
+    };
+
+    let mut writer = options.output.writer()?;
+    writer.write_all(&output)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Invokes `operation` once per line of `input`, writing each result as soon as
+/// it's ready. A line that fails produces the same error JSON the CLI would exit
+/// with rather than stopping the batch.
+fn batch(
+    module: &Module,
+    operation: &str,
+    input: Box<dyn BufRead>,
+    output: &OutputOptions,
+) -> Result<(), Failure> {
+    let mut writer = output.writer()?;
+    let mut first_failure = None;
+    let mut failed = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = input::parse(&line)
+            .map_err(Failure::from)
+            .and_then(|data| Ok(module.invoke::<_, serde_json::Value>(operation, &data)?));
+        let result = match result {
+            Ok(result) => result,
+            Err(failure) => {
+                warn!("Line {} failed: {:?}", number + 1, failure);
+                failed += 1;
+                first_failure.get_or_insert(failure.kind);
+                failure.to_json()
+            }
+        };
+        writeln!(writer, "{}", result)?;
+        writer.flush()?;
+    }
+    match first_failure {
+        Some(kind) => Err(Failure::new(
+            kind,
+            anyhow::anyhow!("{} lines failed", failed),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The positional arguments are only optional so subcommands can be used instead.
+fn required<T>(value: Option<T>, name: &str) -> T {
+    value.unwrap_or_else(|| {
+        structopt::clap::Error::with_description(
+            &format!(
+                "The following required argument was not provided: <{}>",
+                name
+            ),
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    debug!("Running  {} with payload: {:?}", options.operation, bytes);
-    let result = module.run(&options.operation, &bytes)?;
-    let unpacked: serde_json::Value = rmp_serde::from_read_ref(&result)?;
+    #[test]
+    fn runs_operations_named_like_subcommands() {
+        let options =
+            CliOptions::from_iter_safe(["wapc-runner", "blog.wasm", "inspect", "post.json"])
+                .unwrap();
+        assert!(options.command.is_none());
+        assert_eq!(options.operation.as_deref(), Some("inspect"));
 
-    Ok(unpacked)
+        let options = CliOptions::from_iter_safe(["wapc-runner", "inspect", "blog.wasm"]).unwrap();
+        assert!(matches!(options.command, Some(Command::Inspect(_))));
+    }
 }
@@ -0,0 +1,150 @@
+use std::{collections::HashSet, sync::OnceLock, time::Duration};
+
+use my_lib::metrics::{Metrics, Outcome};
+use prometheus::{
+    exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Collects a module's call metrics in Prometheus form, for `serve` to expose
+/// at GET /metrics.
+pub(crate) struct Prometheus {
+    registry: Registry,
+    calls: IntCounterVec,
+    call_seconds: HistogramVec,
+    payload_bytes: HistogramVec,
+    host_calls: IntCounterVec,
+    host_call_seconds: HistogramVec,
+    /// The operations the module's interface declares, when it has one.
+    declared: OnceLock<HashSet<String>>,
+}
+
+/// The operation label for calls to operations the module doesn't have, so
+/// requests for arbitrary paths can't create new series.
+const UNKNOWN: &str = "unknown";
+
+impl Prometheus {
+    pub(crate) fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("wapc".to_owned()), None)?;
+        let calls = IntCounterVec::new(
+            Opts::new("calls_total", "Guest calls by operation and outcome."),
+            &["operation", "outcome"],
+        )?;
+        let call_seconds = HistogramVec::new(
+            HistogramOpts::new("call_duration_seconds", "How long guest calls took."),
+            &["operation"],
+        )?;
+        let payload_bytes = HistogramVec::new(
+            HistogramOpts::new("call_payload_bytes", "The size of guest call payloads.")
+                .buckets(exponential_buckets(64.0, 4.0, 8)?),
+            &["operation"],
+        )?;
+        let host_calls = IntCounterVec::new(
+            Opts::new(
+                "host_calls_total",
+                "Host calls by binding, namespace, operation and outcome.",
+            ),
+            &["binding", "namespace", "operation", "outcome"],
+        )?;
+        let host_call_seconds = HistogramVec::new(
+            HistogramOpts::new("host_call_duration_seconds", "How long host calls took."),
+            &["binding", "namespace", "operation"],
+        )?;
+        registry.register(Box::new(calls.clone()))?;
+        registry.register(Box::new(call_seconds.clone()))?;
+        registry.register(Box::new(payload_bytes.clone()))?;
+        registry.register(Box::new(host_calls.clone()))?;
+        registry.register(Box::new(host_call_seconds.clone()))?;
+        Ok(Prometheus {
+            registry,
+            calls,
+            call_seconds,
+            payload_bytes,
+            host_calls,
+            host_call_seconds,
+            declared: OnceLock::new(),
+        })
+    }
+
+    /// Labels calls to any operation but these as unknown.
+    pub(crate) fn declare(&self, operations: &[&str]) {
+        let operations = operations.iter().map(|op| (*op).to_owned()).collect();
+        let _ = self.declared.set(operations);
+    }
+
+    fn operation_label<'a>(&self, operation: &'a str, outcome: Outcome) -> &'a str {
+        let declared = self
+            .declared
+            .get()
+            .is_none_or(|declared| declared.contains(operation));
+        if declared && outcome != Outcome::UnknownOperation {
+            operation
+        } else {
+            UNKNOWN
+        }
+    }
+
+    /// The metrics in Prometheus' text exposition format.
+    pub(crate) fn render(&self) -> prometheus::Result<String> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+impl Metrics for Prometheus {
+    fn guest_call(
+        &self,
+        operation: &str,
+        payload_size: usize,
+        duration: Duration,
+        outcome: Outcome,
+    ) {
+        let operation = self.operation_label(operation, outcome);
+        self.calls
+            .with_label_values(&[operation, outcome.as_str()])
+            .inc();
+        self.call_seconds
+            .with_label_values(&[operation])
+            .observe(duration.as_secs_f64());
+        self.payload_bytes
+            .with_label_values(&[operation])
+            .observe(payload_size as f64);
+    }
+
+    fn host_call(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        duration: Duration,
+        outcome: Outcome,
+    ) {
+        self.host_calls
+            .with_label_values(&[binding, namespace, operation, outcome.as_str()])
+            .inc();
+        self.host_call_seconds
+            .with_label_values(&[binding, namespace, operation])
+            .observe(duration.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_unknown_operations_as_unknown() -> prometheus::Result<()> {
+        let metrics = Prometheus::new()?;
+        metrics.guest_call("hello", 0, Duration::ZERO, Outcome::Ok);
+        metrics.guest_call("nope", 0, Duration::ZERO, Outcome::UnknownOperation);
+        metrics.declare(&["hello"]);
+        metrics.guest_call("other", 0, Duration::ZERO, Outcome::Error);
+
+        let text = metrics.render()?;
+        assert!(text.contains(r#"wapc_calls_total{operation="hello",outcome="ok"} 1"#));
+        assert!(
+            text.contains(r#"wapc_calls_total{operation="unknown",outcome="unknown_operation"} 1"#)
+        );
+        assert!(text.contains(r#"wapc_calls_total{operation="unknown",outcome="error"} 1"#));
+        assert!(!text.contains("nope") && !text.contains("other"));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+#[cfg(feature = "wasmtime")]
+use std::path::PathBuf;
+use std::{fs, path::Path};
+
+#[cfg(feature = "wasmtime")]
+use my_lib::aot::CompileCache;
+use my_lib::{
+    codec::CodecKind,
+    config::{ModuleConfig, TrustPolicy, WasiConfig},
+    engine::EngineKind,
+    signing::{self, PublicKey},
+    Module, ModuleBuilder,
+};
+use structopt::StructOpt;
+
+// Options that control how a module is loaded, shared by every command that runs one.
+// A plain comment, since a doc comment would replace the app description in --help.
+#[derive(StructOpt)]
+pub(crate) struct ModuleOptions {
+    /// The wire format the module speaks: msgpack, msgpack-array, json, cbor, or raw.
+    #[structopt(long, default_value = "msgpack")]
+    pub(crate) codec: CodecKind,
+
+    /// Give a WASI guest access to a host directory, or to HOST at GUEST with GUEST::HOST.
+    #[structopt(long = "dir", number_of_values = 1)]
+    pub(crate) dirs: Vec<String>,
+
+    /// Set an environment variable for a WASI guest, as KEY=VALUE.
+    #[structopt(long = "env", number_of_values = 1, parse(try_from_str = parse_env))]
+    pub(crate) env: Vec<(String, String)>,
+
+    /// Pass an argument to a WASI guest.
+    #[structopt(long = "arg", number_of_values = 1)]
+    pub(crate) args: Vec<String>,
+
+    /// Only load modules signed by this key, given in hex or as a .pub file. Can be repeated.
+    #[structopt(long = "trusted-key", number_of_values = 1, parse(try_from_str = parse_key))]
+    pub(crate) trusted_keys: Vec<PublicKey>,
+
+    /// The engine to run the module on, wasmtime or wasmi, as enabled in this build.
+    #[structopt(long)]
+    pub(crate) engine: Option<EngineKind>,
+
+    /// Cache compiled modules in the user's cache directory, to skip compiling them next time.
+    #[cfg(feature = "wasmtime")]
+    #[structopt(long)]
+    pub(crate) cache: bool,
+
+    /// Cache compiled modules here instead. Only use a directory no one else can write to.
+    #[cfg(feature = "wasmtime")]
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) cache_dir: Option<PathBuf>,
+}
+
+impl ModuleOptions {
+    pub(crate) fn builder(&self, file_path: &Path) -> ModuleBuilder {
+        let builder = self.uncached_builder(file_path);
+        #[cfg(feature = "wasmtime")]
+        if let Some(cache) = self.cache() {
+            return builder.cache(cache);
+        }
+        builder
+    }
+
+    /// Like [ModuleOptions::builder], but always compiling the module.
+    pub(crate) fn uncached_builder(&self, file_path: &Path) -> ModuleBuilder {
+        let config = ModuleConfig {
+            wasi: self.wasi(file_path),
+            trust: self.trust(),
+            ..Default::default()
+        };
+        let mut builder = Module::builder().codec(self.codec).config(config);
+        if let Some(engine) = self.engine {
+            builder = builder.engine(engine);
+        }
+        builder
+    }
+
+    /// The engine picked with --engine, or the default one.
+    pub(crate) fn engine(&self) -> EngineKind {
+        self.engine.unwrap_or_default()
+    }
+
+    /// Compiled modules run as native code, so caching them is opt-in.
+    #[cfg(feature = "wasmtime")]
+    pub(crate) fn cache(&self) -> Option<CompileCache> {
+        let dir = match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None if self.cache => dirs::cache_dir()?.join("wapc-runner"),
+            None => return None,
+        };
+        Some(CompileCache::new(dir))
+    }
+
+    /// Signatures are only checked when a trusted key is passed.
+    fn trust(&self) -> Option<TrustPolicy> {
+        if self.trusted_keys.is_empty() {
+            return None;
+        }
+        Some(TrustPolicy {
+            trusted_keys: self.trusted_keys.clone(),
+        })
+    }
+
+    /// WASI is only enabled when one of the WASI flags is passed.
+    fn wasi(&self, file_path: &Path) -> Option<WasiConfig> {
+        if self.dirs.is_empty() && self.env.is_empty() && self.args.is_empty() {
+            return None;
+        }
+        let mut wasi = WasiConfig::default().arg(file_path.to_string_lossy());
+        for arg in &self.args {
+            wasi = wasi.arg(arg);
+        }
+        for (key, value) in &self.env {
+            wasi = wasi.env(key, value);
+        }
+        for dir in &self.dirs {
+            wasi = match dir.split_once("::") {
+                Some((guest, host)) => wasi.map_dir(guest, host),
+                None => wasi.preopen_dir(dir),
+            };
+        }
+        Some(wasi)
+    }
+}
+
+fn parse_key(s: &str) -> Result<PublicKey, String> {
+    let hex = match fs::read_to_string(s) {
+        Ok(contents) => contents,
+        Err(_) => s.to_owned(),
+    };
+    signing::parse_public_key(&hex).map_err(|e| e.to_string())
+}
+
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("Expected KEY=VALUE, got '{}'", s))
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use my_lib::{
+    codec::{Codec, MessagePack},
+    error::Error,
+};
+use structopt::StructOpt;
+
+// Options that control where and how results are written.
+#[derive(StructOpt)]
+pub(crate) struct OutputOptions {
+    /// How to write the result: json, pretty, msgpack, or raw (the guest's bytes, undecoded).
+    #[structopt(long, conflicts_with = "batch")]
+    pub(crate) output: Option<OutputFormat>,
+
+    /// Write the result to a file instead of stdout.
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) out: Option<PathBuf>,
+}
+
+impl OutputOptions {
+    pub(crate) fn format(&self) -> OutputFormat {
+        self.output.unwrap_or_default()
+    }
+
+    /// Where results go, a file if `--out` was passed or stdout if not.
+    pub(crate) fn writer(&self) -> Result<Box<dyn Write>, Failure> {
+        match &self.out {
+            Some(path) => {
+                let file = fs::File::create(path).map_err(|e| Failure::io(path, e))?;
+                Ok(Box::new(io::BufWriter::new(file)))
+            }
+            None => Ok(Box::new(io::stdout())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum OutputFormat {
+    #[default]
+    Json,
+    Pretty,
+    Msgpack,
+    Raw,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "pretty" => Ok(OutputFormat::Pretty),
+            "msgpack" => Ok(OutputFormat::Msgpack),
+            "raw" => Ok(OutputFormat::Raw),
+            _ => Err(format!(
+                "Unknown output format '{}', expected json, pretty, msgpack, or raw",
+                s
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Renders a decoded result. Text formats end in a newline.
+    pub(crate) fn render(self, value: &serde_json::Value) -> Result<Vec<u8>, Failure> {
+        let mut bytes = match self {
+            OutputFormat::Json | OutputFormat::Raw => serde_json::to_vec(value),
+            OutputFormat::Pretty => serde_json::to_vec_pretty(value),
+            OutputFormat::Msgpack => return Ok(MessagePack.encode(value)?),
+        }
+        .map_err(|e| Error::EncodeFailed(e.to_string()))?;
+        bytes.push(b'\n');
+        Ok(bytes)
+    }
+}
+
+/// What went wrong, which decides the process's exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// The module couldn't be read or instantiated.
+    Load,
+    /// The module loaded but the operation failed.
+    Guest,
+    /// The input or output couldn't be parsed, encoded or decoded.
+    Codec,
+    /// Input couldn't be read or output couldn't be written.
+    Io,
+}
+
+impl Kind {
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            Kind::Load => 2,
+            Kind::Guest => 3,
+            Kind::Codec => 4,
+            Kind::Io => 5,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Load => "load",
+            Kind::Guest => "guest",
+            Kind::Codec => "codec",
+            Kind::Io => "io",
+        }
+    }
+}
+
+/// An error from the run path, tagged with the [Kind] that decides its exit code.
+#[derive(Debug)]
+pub(crate) struct Failure {
+    pub(crate) kind: Kind,
+    error: anyhow::Error,
+}
+
+impl Failure {
+    pub(crate) fn new(kind: Kind, error: impl Into<anyhow::Error>) -> Self {
+        Failure {
+            kind,
+            error: error.into(),
+        }
+    }
+
+    pub(crate) fn io(path: &Path, error: io::Error) -> Self {
+        Failure::new(
+            Kind::Io,
+            anyhow::Error::new(error).context(format!("Could not write {}", path.display())),
+        )
+    }
+
+    /// The error as `{"error": {"kind": ..., "code": ..., "message": ...}}`,
+    /// with my-lib's error code as `"type"` when the error came from it.
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "kind": self.kind.name(),
+            "code": self.kind.exit_code(),
+            "message": format!("{:#}", self.error),
+        });
+        if let Some(e) = self.error.downcast_ref::<Error>() {
+            json["type"] = e.code().into();
+        }
+        serde_json::json!({ "error": json })
+    }
+}
+
+/// Errors that weren't tagged where they happened are classified by type.
+impl<E: Into<anyhow::Error>> From<E> for Failure {
+    fn from(error: E) -> Self {
+        let error = error.into();
+        let kind = if let Some(e) = error.downcast_ref::<Error>() {
+            match e {
+                Error::FileNotReadable(..) => Kind::Io,
+                Error::EncodeFailed(_) | Error::DecodeFailed(_) | Error::InvalidInput(..) => {
+                    Kind::Codec
+                }
+                _ => Kind::Guest,
+            }
+        } else if error.downcast_ref::<io::Error>().is_some() {
+            Kind::Io
+        } else {
+            // Everything else on the run path comes from parsing input.
+            Kind::Codec
+        };
+        Failure { kind, error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn classifies_untagged_errors() {
+        let kind = |error: anyhow::Error| Failure::from(error).kind;
+        assert_eq!(kind(Error::ModuleClosed.into()), Kind::Guest);
+        assert_eq!(kind(Error::DecodeFailed("eof".into()).into()), Kind::Codec);
+        let unreadable = Error::FileNotReadable("blog.wasm".into(), "gone".into());
+        assert_eq!(kind(unreadable.into()), Kind::Io);
+        let io = io::Error::new(io::ErrorKind::BrokenPipe, "closed");
+        assert_eq!(kind(io.into()), Kind::Io);
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(kind(json.into()), Kind::Codec);
+    }
+
+    #[test]
+    fn gives_each_kind_its_own_exit_code() {
+        let codes = [Kind::Load, Kind::Guest, Kind::Codec, Kind::Io].map(Kind::exit_code);
+        assert_eq!(codes, [2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn reports_failures_as_json() {
+        let failure = Failure::new(Kind::Guest, Error::ModuleClosed);
+        let json = failure.to_json();
+        assert_eq!(json["error"]["kind"], "guest");
+        assert_eq!(json["error"]["code"], 3);
+        assert_eq!(json["error"]["type"], Error::ModuleClosed.code());
+
+        let json = Failure::new(Kind::Load, anyhow::anyhow!("no such file")).to_json();
+        assert_eq!(json["error"]["message"], "no such file");
+        assert!(json["error"].get("type").is_none());
+    }
+
+    #[test]
+    fn renders_each_format() -> Result<(), Failure> {
+        let value = json!({ "id": 1 });
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+        assert_eq!(OutputFormat::Json.render(&value)?, b"{\"id\":1}\n");
+        assert_eq!(OutputFormat::Pretty.render(&value)?, b"{\n  \"id\": 1\n}\n");
+        let msgpack = OutputFormat::Msgpack.render(&value)?;
+        assert_eq!(MessagePack.decode::<serde_json::Value>(&msgpack)?, value);
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use std::{path::PathBuf, time::Instant};
+
+use my_lib::Module;
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    validate::Validator, Context, Editor, Helper,
+};
+use structopt::StructOpt;
+
+use crate::options::ModuleOptions;
+
+const COMMANDS: &[&str] = &[":help", ":ops", ":reload", ":quit"];
+
+const HELP: &str = "\
+<operation> [json]  Invoke an operation, with null input if no JSON is given
+:ops                List the operations the module declares
+:reload             Load the wasm file again, e.g. after rebuilding it
+:help               Show this message
+:quit               Exit (or Ctrl-D)";
+
+#[derive(StructOpt)]
+pub(crate) struct ReplOptions {
+    /// The WebAssembly file to load.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+
+    #[structopt(flatten)]
+    pub(crate) module: ModuleOptions,
+}
+
+/// Completes operation names at the start of a line, and REPL commands.
+struct Operations(Vec<String>);
+
+impl Completer for Operations {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let word = &line[..pos];
+        // Only the first word names an operation, the rest is the payload.
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        let candidates = self
+            .0
+            .iter()
+            .map(String::as_str)
+            .chain(COMMANDS.iter().copied())
+            .filter(|candidate| candidate.starts_with(word))
+            .map(str::to_owned)
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for Operations {
+    type Hint = String;
+}
+
+impl Highlighter for Operations {}
+
+impl Validator for Operations {}
+
+impl Helper for Operations {}
+
+pub(crate) fn repl(options: ReplOptions) -> anyhow::Result<()> {
+    let builder = options.module.builder(&options.file_path);
+    let mut module = builder.clone().from_file(&options.file_path)?;
+    println!(
+        "Loaded {}, type :help for commands",
+        options.file_path.display()
+    );
+
+    let mut editor = Editor::new();
+    editor.set_helper(Some(Operations(operations(&module))));
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line);
+
+        match line {
+            ":quit" | ":q" => break,
+            ":help" => println!("{}", HELP),
+            ":ops" => {
+                for operation in operations(&module) {
+                    println!("{}", operation);
+                }
+            }
+            ":reload" => match builder.clone().from_file(&options.file_path) {
+                Ok(reloaded) => {
+                    module = reloaded;
+                    editor.set_helper(Some(Operations(operations(&module))));
+                    println!("Reloaded {}", options.file_path.display());
+                }
+                Err(e) => println!("Reload failed, keeping the previous version: {}", e),
+            },
+            _ if line.starts_with(':') => println!("Unknown command {}, try :help", line),
+            _ => {
+                if let Err(e) = invoke(&module, line) {
+                    println!("Error: {}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invoke(module: &Module, line: &str) -> anyhow::Result<()> {
+    let (operation, input) = parse(line)?;
+    let start = Instant::now();
+    let result: serde_json::Value = module.invoke(operation, &input)?;
+    let elapsed = start.elapsed();
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    println!("({:?})", elapsed);
+    Ok(())
+}
+
+/// Splits a line into the operation and its JSON input, null if there is none.
+fn parse(line: &str) -> anyhow::Result<(&str, serde_json::Value)> {
+    let (operation, json) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let input = match json.trim() {
+        "" => serde_json::Value::Null,
+        json => serde_json::from_str(json)?,
+    };
+    Ok((operation, input))
+}
+
+fn operations(module: &Module) -> Vec<String> {
+    module.operations().into_iter().map(str::to_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rustyline::history::History;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_operation_and_input() -> anyhow::Result<()> {
+        assert_eq!(parse("list")?, ("list", serde_json::Value::Null));
+        assert_eq!(
+            parse(r#"create  {"title": "a b"}"#)?,
+            ("create", json!({ "title": "a b" }))
+        );
+        assert!(parse("create {").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn completes_operations_then_nothing() -> rustyline::Result<()> {
+        let operations = Operations(vec!["create".to_owned(), "list".to_owned()]);
+        let history = History::new();
+        let context = Context::new(&history);
+
+        assert_eq!(
+            operations.complete("cr", 2, &context)?,
+            (0, vec!["create".to_owned()])
+        );
+        assert_eq!(
+            operations.complete(":r", 2, &context)?,
+            (0, vec![":reload".to_owned()])
+        );
+        // The payload after the operation isn't completed.
+        assert_eq!(operations.complete("create l", 8, &context)?, (8, vec![]));
+        Ok(())
+    }
+}
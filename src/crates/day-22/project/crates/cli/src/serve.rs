@@ -0,0 +1,349 @@
+use std::{
+    convert::Infallible, future::Future, net::SocketAddr, num::NonZeroUsize, path::PathBuf,
+    sync::Arc, thread, time::Duration,
+};
+
+use hyper::{
+    header::{HeaderValue, ACCEPT, CONTENT_TYPE},
+    server::conn::AddrIncoming,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use my_lib::{
+    async_module::AsyncModule,
+    codec::{Codec, MessagePack},
+    error::Error,
+    limits::Limits,
+};
+use structopt::StructOpt;
+use tokio::time::Instant;
+
+use crate::{metrics::Prometheus, options::ModuleOptions};
+
+const JSON: &str = "application/json";
+const MSGPACK: &str = "application/msgpack";
+const METRICS: &str = "/metrics";
+
+#[derive(StructOpt)]
+pub(crate) struct ServeOptions {
+    /// The WebAssembly file to serve.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+
+    /// The address to listen on.
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    pub(crate) address: SocketAddr,
+
+    /// How many seconds a request may take before it fails with 504 Gateway Timeout.
+    #[structopt(long, default_value = "30")]
+    pub(crate) timeout: u64,
+
+    /// How many instances of the module serve requests at once. Defaults to
+    /// the number of CPUs.
+    #[structopt(long)]
+    pub(crate) instances: Option<usize>,
+
+    #[structopt(flatten)]
+    pub(crate) module: ModuleOptions,
+}
+
+pub(crate) fn serve(options: ServeOptions) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(listen(options))
+}
+
+async fn listen(options: ServeOptions) -> anyhow::Result<()> {
+    let timeout = Duration::from_secs(options.timeout);
+    let metrics = Arc::new(Prometheus::new()?);
+    let builder = options
+        .module
+        .builder(&options.file_path)
+        .limits(Limits {
+            // Engines that can't interrupt a guest leave it to finish after the
+            // request has timed out.
+            timeout: options.module.engine().enforces_limits().then_some(timeout),
+            ..Default::default()
+        })
+        .metrics(metrics.clone());
+    let instances = options
+        .instances
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+    anyhow::ensure!(instances > 0, "--instances must be at least 1");
+    let module = AsyncModule::from_file(builder, &options.file_path, instances).await?;
+    let operations = module.info().operations();
+    if !operations.is_empty() {
+        metrics.declare(&operations);
+    }
+    info!("Module loaded with {} instances", instances);
+
+    let incoming = AddrIncoming::bind(&options.address)?;
+    info!("Listening on http://{}", incoming.local_addr());
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Shutting down, waiting for in-flight requests to finish");
+    };
+    run(incoming, Arc::new(module), metrics, timeout, shutdown).await?;
+    Ok(())
+}
+
+/// Serves requests on `incoming` until `shutdown` resolves.
+async fn run(
+    incoming: AddrIncoming,
+    module: Arc<AsyncModule>,
+    metrics: Arc<Prometheus>,
+    timeout: Duration,
+    shutdown: impl Future<Output = ()>,
+) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_conn| {
+        let module = module.clone();
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(module.clone(), metrics.clone(), request, timeout)
+            }))
+        }
+    });
+    Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+async fn handle(
+    module: Arc<AsyncModule>,
+    metrics: Arc<Prometheus>,
+    request: Request<Body>,
+    timeout: Duration,
+) -> Result<Response<Body>, Infallible> {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+
+    if method == Method::GET && path == METRICS {
+        return Ok(match metrics.render() {
+            Ok(text) => Response::builder()
+                .header(CONTENT_TYPE, prometheus::TEXT_FORMAT)
+                .body(Body::from(text))
+                .unwrap(),
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), false),
+        });
+    }
+
+    let response = match tokio::time::timeout(timeout, respond(&module, request)).await {
+        Ok(response) => response,
+        Err(_) => error_response(StatusCode::GATEWAY_TIMEOUT, "request timed out", false),
+    };
+
+    info!(
+        "{} {} {} in {:?}",
+        method,
+        path,
+        response.status().as_u16(),
+        start.elapsed()
+    );
+    Ok(response)
+}
+
+async fn respond(module: &AsyncModule, request: Request<Body>) -> Response<Body> {
+    let msgpack_response = accepts_msgpack(&request);
+    if request.method() != Method::POST {
+        return error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "operations are invoked with POST, metrics are at GET /metrics",
+            msgpack_response,
+        );
+    }
+    let operation = request.uri().path().trim_start_matches('/').to_owned();
+    if operation.is_empty() {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "POST to /{operation}",
+            msgpack_response,
+        );
+    }
+    let msgpack_request = is_msgpack(request.headers().get(CONTENT_TYPE));
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string(), msgpack_response),
+    };
+    let input: serde_json::Value = match decode(&body, msgpack_request) {
+        Ok(input) => input,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string(), msgpack_response),
+    };
+
+    match module
+        .invoke::<_, serde_json::Value>(&operation, &input)
+        .await
+    {
+        Ok(output) => encode(&output, msgpack_response),
+        Err(e) => {
+            let mut response = encode(
+                &serde_json::json!({ "error": e.to_string(), "code": e.code() }),
+                msgpack_response,
+            );
+            *response.status_mut() = status_for(&e);
+            response
+        }
+    }
+}
+
+fn decode(body: &[u8], msgpack: bool) -> Result<serde_json::Value, Error> {
+    if msgpack {
+        MessagePack.decode(body)
+    } else {
+        serde_json::from_slice(body).map_err(|e| Error::DecodeFailed(e.to_string()))
+    }
+}
+
+fn encode(value: &serde_json::Value, msgpack: bool) -> Response<Body> {
+    let (body, content_type) = if msgpack {
+        (MessagePack.encode(value), MSGPACK)
+    } else {
+        (
+            serde_json::to_vec(value).map_err(|e| Error::EncodeFailed(e.to_string())),
+            JSON,
+        )
+    };
+    match body {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), false),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str, msgpack: bool) -> Response<Body> {
+    let mut response = encode(&serde_json::json!({ "error": message }), msgpack);
+    *response.status_mut() = status;
+    response
+}
+
+fn status_for(error: &Error) -> StatusCode {
+    match error {
+        Error::InvalidInput(..) | Error::EncodeFailed(_) | Error::DecodeFailed(_) => {
+            StatusCode::BAD_REQUEST
+        }
+        Error::UnknownOperation { .. } => StatusCode::NOT_FOUND,
+        Error::Timeout(..) => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn accepts_msgpack(request: &Request<Body>) -> bool {
+    is_msgpack(request.headers().get(ACCEPT))
+}
+
+fn is_msgpack(header: Option<&HeaderValue>) -> bool {
+    header
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("msgpack"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Client;
+    use my_lib::Module;
+
+    use super::*;
+
+    /// A guest whose __guest_call passes its payload to host_call("db", "", "get")
+    /// and responds with whatever the host returned.
+    const FORWARDING: &str = r#"(module
+        (import "wapc" "__guest_request" (func $guest_request (param i32 i32)))
+        (import "wapc" "__host_call"
+            (func $host_call (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+        (import "wapc" "__host_response_len" (func $host_response_len (result i32)))
+        (import "wapc" "__host_response" (func $host_response (param i32)))
+        (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "dbget")
+        (func (export "__guest_call") (param $op_len i32) (param $len i32) (result i32)
+            (call $guest_request (i32.const 16) (i32.const 64))
+            (drop (call $host_call
+                (i32.const 0) (i32.const 2)
+                (i32.const 0) (i32.const 0)
+                (i32.const 2) (i32.const 3)
+                (i32.const 64) (local.get $len)))
+            (call $host_response (i32.const 1024))
+            (call $guest_response (i32.const 1024) (call $host_response_len))
+            (i32.const 1)))"#;
+
+    /// Serves an echoing module whose host call takes `delay` on a free port.
+    async fn start(delay: Duration, timeout: Duration) -> SocketAddr {
+        let builder = Module::builder().async_handler("db", "", "get", move |payload| async move {
+            tokio::time::sleep(delay).await;
+            Ok(payload)
+        });
+        let bytes = wat::parse_str(FORWARDING).unwrap();
+        let module = AsyncModule::new(builder, bytes, 1).await.unwrap();
+        let incoming = AddrIncoming::bind(&([127, 0, 0, 1], 0).into()).unwrap();
+        let address = incoming.local_addr();
+        let metrics = Arc::new(Prometheus::new().unwrap());
+        let shutdown = std::future::pending();
+        tokio::spawn(run(incoming, Arc::new(module), metrics, timeout, shutdown));
+        address
+    }
+
+    async fn request(
+        address: SocketAddr,
+        method: Method,
+        path: &str,
+        body: &str,
+    ) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", address, path))
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = Client::new().request(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn routes_requests() {
+        let address = start(Duration::ZERO, Duration::from_secs(5)).await;
+
+        let (status, body) = request(address, Method::POST, "/echo", r#"{"title":"hi"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"title":"hi"}"#);
+        let (status, _) = request(address, Method::GET, METRICS, "").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = request(address, Method::GET, "/echo", "").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let (status, _) = request(address, Method::POST, "/", "{}").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = request(address, Method::POST, "/echo", "not json").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("error"), "{}", body);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn times_out_slow_requests() {
+        let delay = Duration::from_millis(500);
+        let address = start(delay, Duration::from_millis(50)).await;
+
+        let (status, body) = request(address, Method::POST, "/echo", "{}").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(body.contains("timed out"), "{}", body);
+        // Let the host call finish before the runtime shuts down under it.
+        tokio::time::sleep(delay).await;
+    }
+
+    #[test]
+    fn maps_errors_to_statuses() {
+        let timeout = Error::Timeout("echo".to_owned(), Duration::from_secs(1));
+        assert_eq!(status_for(&timeout), StatusCode::GATEWAY_TIMEOUT);
+        let decode = Error::DecodeFailed("eof".to_owned());
+        assert_eq!(status_for(&decode), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status_for(&Error::ModuleClosed),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use my_lib::signing;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub(crate) struct KeygenOptions {
+    /// Where to write the secret key, which must not exist yet. The public key is written next to
+    /// it with a .pub extension.
+    #[structopt(short, long, parse(from_os_str))]
+    pub(crate) output: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub(crate) struct SignOptions {
+    /// The WebAssembly file to sign.
+    #[structopt(parse(from_os_str))]
+    pub(crate) file_path: PathBuf,
+
+    /// The secret key to sign with, as written by keygen.
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) key: PathBuf,
+
+    /// Where to write the signed module.
+    #[structopt(short, long, parse(from_os_str), required_unless = "detached")]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Write the signature to <file>.sig instead of embedding it in the module.
+    #[structopt(long, conflicts_with = "output")]
+    pub(crate) detached: bool,
+}
+
+pub(crate) fn keygen(options: KeygenOptions) -> anyhow::Result<()> {
+    let key = signing::generate_key();
+    let public_path = options.output.with_extension("pub");
+    write_secret(&options.output, &signing::encode_secret_key(&key))?;
+    fs::write(&public_path, signing::encode_public_key(&key.public))?;
+    info!(
+        "Wrote {} and {}",
+        options.output.display(),
+        public_path.display()
+    );
+    Ok(())
+}
+
+/// Writes a secret key only its owner can read, never replacing an existing one.
+fn write_secret(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Could not create {}: {}", path.display(), e))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn sign(options: SignOptions) -> anyhow::Result<()> {
+    let key = signing::parse_secret_key(&fs::read_to_string(&options.key)?)?;
+    let bytes = fs::read(&options.file_path)?;
+    let (path, contents) = match options.output {
+        Some(output) if !options.detached => (output, signing::sign(&bytes, &key)?),
+        _ => (
+            signing::detached_path(&options.file_path),
+            signing::sign_detached(&bytes, &key)?,
+        ),
+    };
+    fs::write(&path, contents)?;
+    info!(
+        "Signed {} with {}, wrote {}",
+        options.file_path.display(),
+        signing::encode_public_key(&key.public),
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::{is_precompiled, Artifact, ENGINE_VERSION, MAGIC};
+use crate::{engine, error::Error, introspect};
+
+/// Compiles a wasm module into an artifact that loads without compiling again.
+pub fn compile(wasm: &[u8]) -> Result<Vec<u8>, Error> {
+    if is_precompiled(wasm) {
+        return Err(Error::invalid_module(
+            "module is already precompiled".to_owned(),
+        ));
+    }
+    // Catch malformed modules with the same errors loading them would give.
+    introspect::inspect(wasm)?;
+    let compiled = engine::compiler::engine()
+        .precompile_module(wasm)
+        .map_err(|e| Error::invalid_module(e.to_string()))?;
+
+    let mut artifact = MAGIC.to_vec();
+    for section in [ENGINE_VERSION.as_bytes(), wasm] {
+        artifact.extend((section.len() as u64).to_le_bytes());
+        artifact.extend(section);
+    }
+    artifact.extend(compiled);
+    Ok(artifact)
+}
+
+/// An on-disk cache of compiled modules, keyed by a hash of the module and the
+/// engine version so upgrading wasmtime never loads stale code.
+///
+/// Entries are run as native code, so the directory must only be writable by
+/// users trusted to run code as you. Entries are only used when the wasm they
+/// were compiled from is the module being loaded.
+#[derive(Debug, Clone)]
+pub struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Self {
+        CompileCache { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The compiled artifact for `wasm`, compiling and storing it on a miss.
+    ///
+    /// A cache that can't be written to only costs a recompile next time, so
+    /// write failures are logged rather than returned.
+    pub fn get_or_compile(&self, wasm: &[u8]) -> Result<Vec<u8>, Error> {
+        let path = self.dir.join(format!("{}.cwasm", key(wasm)));
+        if let Ok(artifact) = fs::read(&path) {
+            match Artifact::parse(&artifact) {
+                Ok(Some(entry)) if entry.wasm == wasm => {
+                    debug!("Loaded compiled module from {}", path.display());
+                    return Ok(artifact);
+                }
+                Ok(Some(_)) => warn!("Ignoring cache entry {} for another module", path.display()),
+                _ => warn!("Ignoring unreadable cache entry {}", path.display()),
+            }
+        }
+
+        let artifact = compile(wasm)?;
+        if let Err(e) = self.store(&path, &artifact) {
+            warn!("Could not cache compiled module: {}", e);
+        }
+        Ok(artifact)
+    }
+
+    fn store(&self, path: &Path, artifact: &[u8]) -> Result<(), Error> {
+        let not_writable =
+            |e: std::io::Error| Error::FileNotWritable(path.to_path_buf(), e.to_string());
+        let mut dir = fs::DirBuilder::new();
+        dir.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            dir.mode(0o700);
+        }
+        dir.create(&self.dir).map_err(not_writable)?;
+        // Write then rename, so a concurrent load never sees half an artifact.
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        fs::write(&partial, artifact).map_err(not_writable)?;
+        fs::rename(&partial, path).map_err(not_writable)?;
+        debug!("Cached compiled module at {}", path.display());
+        Ok(())
+    }
+}
+
+/// The cache key for `wasm`: a SHA-256 of the engine version and the module.
+pub fn key(wasm: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(ENGINE_VERSION);
+    hasher.update(wasm);
+    let mut key = String::with_capacity(64);
+    for byte in hasher.finalize() {
+        key.push_str(&format!("{:02x}", byte));
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_precompiled_module() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("my-lib-aot-{}", std::process::id()));
+        let cache = CompileCache::new(&dir);
+        let wasm = fs::read("./tests/test.wasm").unwrap();
+
+        let artifact = cache.get_or_compile(&wasm)?;
+        assert!(dir.join(format!("{}.cwasm", key(&wasm))).exists());
+        assert_eq!(cache.get_or_compile(&wasm)?, artifact);
+
+        // Native code only loads when asked for, never just because of the magic.
+        assert!(matches!(
+            crate::Module::new(&artifact),
+            Err(Error::InvalidArtifact(_))
+        ));
+        let path = dir.join("test.cwasm");
+        fs::write(&path, &artifact).unwrap();
+        let module = crate::Module::from_precompiled(&path)?;
+        let result: String = module.invoke("hello", &"World")?;
+        assert_eq!(result, "Hello, World.");
+
+        let module = crate::Module::builder().cache(cache.clone()).build(&wasm)?;
+        let result: String = module.invoke("hello", &"World")?;
+        assert_eq!(result, "Hello, World.");
+
+        // An entry compiled from other wasm is recompiled rather than run.
+        let other = compile(&wat::parse_str("(module)").unwrap())?;
+        fs::write(dir.join(format!("{}.cwasm", key(&wasm))), &other).unwrap();
+        assert_eq!(cache.get_or_compile(&wasm)?, artifact);
+
+        fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::convert::TryInto;
+
+use crate::error::Error;
+
+#[cfg(feature = "wasmtime")]
+mod cache;
+#[cfg(feature = "wasmtime")]
+pub use cache::{compile, key, CompileCache};
+
+/// Marks a file as a precompiled module rather than wasm, whose magic is `\0asm`.
+const MAGIC: &[u8] = b"\0wapc-aot";
+
+/// The wasmtime version artifacts are compiled with. Keep in step with the
+/// wasmtime dependency, since compiled code only loads into the version that
+/// produced it.
+pub const ENGINE_VERSION: &str = "wasmtime-0.30";
+
+/// A module compiled ahead of time, along with the wasm it was compiled from so
+/// it can still be inspected and validated.
+///
+/// Laid out as the magic, then the length-prefixed engine version and wasm,
+/// then the compiled code.
+pub(crate) struct Artifact<'a> {
+    pub(crate) wasm: &'a [u8],
+    /// Only wasmtime can run compiled code, other engines run the wasm.
+    #[cfg_attr(not(feature = "wasmtime"), allow(dead_code))]
+    pub(crate) compiled: &'a [u8],
+}
+
+impl<'a> Artifact<'a> {
+    /// Splits up an artifact, or returns `None` if `bytes` isn't one.
+    pub(crate) fn parse(bytes: &'a [u8]) -> Result<Option<Self>, Error> {
+        let rest = match bytes.strip_prefix(MAGIC) {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+        let (engine, rest) = split(rest)?;
+        if engine != ENGINE_VERSION.as_bytes() {
+            return Err(Error::InvalidArtifact(format!(
+                "compiled for {}, but this build runs {}",
+                String::from_utf8_lossy(engine),
+                ENGINE_VERSION
+            )));
+        }
+        let (wasm, compiled) = split(rest)?;
+        Ok(Some(Artifact { wasm, compiled }))
+    }
+}
+
+fn split(bytes: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let truncated = || Error::InvalidArtifact("file is truncated".to_owned());
+    let len = bytes.get(..8).ok_or_else(truncated)?;
+    let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+    let rest = &bytes[8..];
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    Ok(rest.split_at(len))
+}
+
+/// Whether `bytes` is a precompiled module rather than wasm.
+pub fn is_precompiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The wasm in `bytes`, whether it's wasm already or a precompiled module.
+pub(crate) fn wasm(bytes: &[u8]) -> Result<&[u8], Error> {
+    Ok(Artifact::parse(bytes)?.map_or(bytes, |artifact| artifact.wasm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_artifacts_from_other_engines() {
+        let mut artifact = MAGIC.to_vec();
+        for section in [&b"wasmtime-0.1"[..], b"\0asm"] {
+            artifact.extend((section.len() as u64).to_le_bytes());
+            artifact.extend(section);
+        }
+        assert!(matches!(
+            Artifact::parse(&artifact),
+            Err(Error::InvalidArtifact(_))
+        ));
+        assert!(matches!(
+            Artifact::parse(&artifact[..MAGIC.len() + 4]),
+            Err(Error::InvalidArtifact(_))
+        ));
+        assert!(matches!(Artifact::parse(b"\0asm\x01\0\0\0"), Ok(None)));
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    codec::{Codec, CodecKind},
+    error::Error,
+    introspect::{self, ModuleInfo},
+    validate_input,
+    worker::{self, Worker},
+    ModuleBuilder,
+};
+
+type Job = worker::Job<oneshot::Sender<Result<Vec<u8>, Error>>>;
+
+/// A set of [crate::Module] instances driven from async code.
+///
+/// Each instance lives on tokio's blocking pool so guest calls never stall the
+/// executor, and calls go to whichever instance is free. Dropping a call's
+/// future only cancels it while it's queued behind busy instances; once an
+/// instance has started running it, the call finishes and its result is
+/// discarded.
+pub struct AsyncModule {
+    sender: mpsc::UnboundedSender<Job>,
+    size: usize,
+    codec: CodecKind,
+    info: ModuleInfo,
+}
+
+impl AsyncModule {
+    /// Instantiates `size` modules from `bytes` with the configuration in
+    /// `builder`. The module is compiled once and shared by every instance.
+    pub async fn new(
+        mut builder: ModuleBuilder,
+        bytes: Vec<u8>,
+        size: usize,
+    ) -> Result<Self, Error> {
+        debug!("Starting async module with {} instances", size);
+        builder.shared = Some(Default::default());
+        let info = introspect::inspect(&bytes)?;
+        let bytes: Arc<[u8]> = bytes.into();
+        let codec = builder.codec;
+        let (sender, receiver) = mpsc::unbounded_channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (ready_tx, mut ready_rx) = mpsc::unbounded_channel();
+
+        for id in 0..size {
+            let builder = builder.clone();
+            let bytes = bytes.clone();
+            let receiver = receiver.clone();
+            let ready_tx = ready_tx.clone();
+            tokio::task::spawn_blocking(move || {
+                let ready = |result| {
+                    let _ = ready_tx.send(result);
+                };
+                if let Some(worker) = Worker::start(id, builder, &bytes, ready) {
+                    worker.serve(|| receiver.lock().ok()?.blocking_recv());
+                }
+            });
+        }
+        drop(ready_tx);
+
+        let module = AsyncModule {
+            sender,
+            size,
+            codec,
+            info,
+        };
+        for _ in 0..size {
+            ready_rx.recv().await.ok_or(Error::ModuleClosed)??;
+        }
+        Ok(module)
+    }
+
+    pub async fn from_file<T: AsRef<Path>>(
+        builder: ModuleBuilder,
+        path: T,
+        size: usize,
+    ) -> Result<Self, Error> {
+        debug!("Loading wasm file from {:?}", path.as_ref());
+        let bytes = tokio::fs::read(path.as_ref())
+            .await
+            .map_err(|e| Error::FileNotReadable(path.as_ref().to_path_buf(), e.to_string()))?;
+        let bytes = crate::signing::attach_detached(path.as_ref(), bytes)?;
+        Self::new(builder.for_file(path.as_ref())?, bytes, size).await
+    }
+
+    /// The exports, imports, and embedded interface of the loaded module.
+    pub fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub async fn run(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        debug!("Invoking {}", operation);
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(Job::new(operation, payload, reply))
+            .map_err(|_| Error::ModuleClosed)?;
+        result.await.map_err(|_| Error::ModuleClosed)?
+    }
+
+    /// The async equivalent of [crate::Module::invoke].
+    pub async fn invoke<I, O>(&self, operation: &str, input: &I) -> Result<O, Error>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        validate_input(&self.info, self.codec, operation, input)?;
+        let payload = self.codec.encode(input)?;
+        let result = self.run(operation, &payload).await?;
+        self.codec.decode(&result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Module;
+
+    #[tokio::test]
+    async fn invokes_operation() -> Result<(), Error> {
+        let module = AsyncModule::from_file(Module::builder(), "./tests/test.wasm", 1).await?;
+
+        let result: String = module.invoke("hello", &"World").await?;
+        assert_eq!(result, "Hello, World.");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_async_host_calls() -> Result<(), Error> {
+        let builder = Module::builder().async_handler("db", "", "get", |key| async move {
+            tokio::task::yield_now().await;
+            Ok([b"value of ".as_slice(), &key].concat())
+        });
+        let module = AsyncModule::new(builder, crate::fixtures::forwarding(), 1).await?;
+
+        let result = module.run("lookup", b"post").await?;
+        assert_eq!(result, b"value of post");
+        Ok(())
+    }
+    #[tokio::test]
+    async fn runs_calls_on_every_instance() -> Result<(), Error> {
+        // Each call waits in its host call for the other, so they only finish
+        // if both instances run at once.
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let builder = Module::builder().async_handler("db", "", "get", move |key| {
+            let barrier = barrier.clone();
+            async move {
+                barrier.wait().await;
+                Ok(key)
+            }
+        });
+        let module = AsyncModule::new(builder, crate::fixtures::forwarding(), 2).await?;
+        assert_eq!(module.size(), 2);
+
+        let (first, second) = tokio::join!(module.run("lookup", b"a"), module.run("lookup", b"b"));
+        assert_eq!(first?, b"a");
+        assert_eq!(second?, b"b");
+        Ok(())
+    }
+}
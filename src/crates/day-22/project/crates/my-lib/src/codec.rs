@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use serde::{
+    de::{
+        value::{self, BytesDeserializer, StrDeserializer},
+        DeserializeOwned, IntoDeserializer,
+    },
+    Serialize,
+};
+
+use crate::error::Error;
+
+/// Encodes payloads sent to the guest and decodes the payloads it returns.
+pub trait Codec {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// MessagePack with named struct fields, what waPC's generated code speaks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        serialize(item)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        deserialize(bytes)
+    }
+}
+
+/// MessagePack with structs encoded as arrays of their field values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackArray;
+
+impl Codec for MessagePackArray {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec(item).map_err(|e| Error::EncodeFailed(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        deserialize(bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(item).map_err(|e| Error::EncodeFailed(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(bytes).map_err(|e| Error::DecodeFailed(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+impl Codec for Cbor {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        serde_cbor::to_vec(item).map_err(|e| Error::EncodeFailed(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        serde_cbor::from_slice(bytes).map_err(|e| Error::DecodeFailed(e.to_string()))
+    }
+}
+
+/// Passes payloads through untouched. Inputs must serialize as a string or
+/// as bytes, and outputs decode as a string when they are valid UTF-8.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Raw;
+
+impl Codec for Raw {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        match serde_json::to_value(item).map_err(|e| Error::EncodeFailed(e.to_string()))? {
+            serde_json::Value::String(string) => Ok(string.into_bytes()),
+            value => serde_json::from_value(value).map_err(|_| {
+                Error::EncodeFailed("raw payloads must be a string or bytes".to_owned())
+            }),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        let result = match std::str::from_utf8(bytes) {
+            Ok(string) => {
+                let de: StrDeserializer<value::Error> = string.into_deserializer();
+                T::deserialize(de)
+            }
+            Err(_) => T::deserialize(BytesDeserializer::<value::Error>::new(bytes)),
+        };
+        result.map_err(|e| Error::DecodeFailed(e.to_string()))
+    }
+}
+
+/// The built-in codecs, selectable at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodecKind {
+    #[default]
+    MessagePack,
+    MessagePackArray,
+    Json,
+    Cbor,
+    Raw,
+}
+
+impl FromStr for CodecKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "msgpack" => Ok(CodecKind::MessagePack),
+            "msgpack-array" => Ok(CodecKind::MessagePackArray),
+            "json" => Ok(CodecKind::Json),
+            "cbor" => Ok(CodecKind::Cbor),
+            "raw" => Ok(CodecKind::Raw),
+            _ => Err(format!(
+                "Unknown codec '{}', expected one of msgpack, msgpack-array, json, cbor, raw",
+                s
+            )),
+        }
+    }
+}
+
+impl Codec for CodecKind {
+    fn encode<T: Serialize>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            CodecKind::MessagePack => MessagePack.encode(item),
+            CodecKind::MessagePackArray => MessagePackArray.encode(item),
+            CodecKind::Json => Json.encode(item),
+            CodecKind::Cbor => Cbor.encode(item),
+            CodecKind::Raw => Raw.encode(item),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        match self {
+            CodecKind::MessagePack => MessagePack.decode(bytes),
+            CodecKind::MessagePackArray => MessagePackArray.decode(bytes),
+            CodecKind::Json => Json.decode(bytes),
+            CodecKind::Cbor => Cbor.decode(bytes),
+            CodecKind::Raw => Raw.decode(bytes),
+        }
+    }
+}
+
+/// Serializes `item` as MessagePack with named struct fields, the same
+/// encoding the guest's generated `serialize` uses.
+pub fn serialize<T: Serialize>(item: &T) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    item.serialize(&mut rmp_serde::Serializer::new(&mut buf).with_struct_map())
+        .map_err(|e| Error::EncodeFailed(e.to_string()))?;
+    Ok(buf)
+}
+
+/// Deserializes a MessagePack payload produced by the guest's generated `serialize`.
+pub fn deserialize<T: DeserializeOwned>(buf: &[u8]) -> Result<T, Error> {
+    rmp_serde::from_read_ref(buf).map_err(|e| Error::DecodeFailed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Blog {
+        title: String,
+        author: String,
+    }
+
+    fn roundtrip<C: Codec>(codec: C) -> Result<(), Error> {
+        let blog = Blog {
+            title: "Tom Sawyer".to_owned(),
+            author: "Mark Twain".to_owned(),
+        };
+        let bytes = codec.encode(&blog)?;
+        assert_eq!(codec.decode::<Blog>(&bytes)?, blog);
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrips_structs() -> Result<(), Error> {
+        roundtrip(MessagePack)?;
+        roundtrip(MessagePackArray)?;
+        roundtrip(Json)?;
+        roundtrip(Cbor)?;
+        Ok(())
+    }
+
+    #[test]
+    fn passes_raw_payloads_through() -> Result<(), Error> {
+        assert_eq!(Raw.encode(&"Potter")?, b"Potter");
+        assert_eq!(Raw.decode::<String>(b"Potter")?, "Potter");
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use ed25519_dalek::PublicKey;
+
+use crate::{limits::Limits, permissions::Permissions};
+
+/// How a [crate::Module] is loaded and what it may do once it runs.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleConfig {
+    pub limits: Limits,
+    /// Runs the guest with WASI when set. Guests built for plain
+    /// `wasm32-unknown-unknown` don't need it.
+    pub wasi: Option<WasiConfig>,
+    /// Only loads modules signed by a trusted key when set. See [crate::signing].
+    pub trust: Option<TrustPolicy>,
+    /// The host calls the module may make. Any call is allowed when unset,
+    /// unless the module has a permissions manifest next to it.
+    pub permissions: Option<Permissions>,
+}
+
+/// The keys a module must be signed by to be loaded.
+#[derive(Debug, Clone, Default)]
+pub struct TrustPolicy {
+    pub trusted_keys: Vec<PublicKey>,
+}
+
+impl TrustPolicy {
+    pub fn trust(mut self, key: PublicKey) -> Self {
+        self.trusted_keys.push(key);
+        self
+    }
+}
+
+/// The environment a WASI guest sees. The guest has no access to the host
+/// filesystem beyond the directories listed here.
+#[derive(Debug, Clone, Default)]
+pub struct WasiConfig {
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Host directories the guest can open at the same path.
+    pub preopened_dirs: Vec<PathBuf>,
+    /// Host directories the guest can open at a different path, as `(guest, host)` pairs.
+    pub mapped_dirs: Vec<(String, PathBuf)>,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+impl WasiConfig {
+    pub fn arg<T: Into<String>>(mut self, arg: T) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn preopen_dir<T: Into<PathBuf>>(mut self, dir: T) -> Self {
+        self.preopened_dirs.push(dir.into());
+        self
+    }
+
+    pub fn map_dir<G: Into<String>, H: Into<PathBuf>>(mut self, guest: G, host: H) -> Self {
+        self.mapped_dirs.push((guest.into(), host.into()));
+        self
+    }
+
+    pub fn stdout(mut self, output: Output) -> Self {
+        self.stdout = output;
+        self
+    }
+
+    pub fn stderr(mut self, output: Output) -> Self {
+        self.stderr = output;
+        self
+    }
+}
+
+/// Where a WASI guest's stdout or stderr goes.
+#[derive(Debug, Clone, Default)]
+pub enum Output {
+    /// To the host process's own stream.
+    #[default]
+    Inherit,
+    Discard,
+    Capture(Captured),
+}
+
+/// What a guest has written to a captured stream. Clones share the same buffer,
+/// so keep one to read what the module's guests write.
+#[derive(Debug, Clone, Default)]
+pub struct Captured(pub(crate) Arc<RwLock<Vec<u8>>>);
+
+impl Captured {
+    /// Everything written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Everything written so far, emptying the buffer.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.write().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[cfg(all(test, feature = "wasmtime"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_guest_output() -> Result<(), crate::error::Error> {
+        // A WASI guest whose wapc_init writes "out\n" to stdout and "err\n" to stderr.
+        let printing = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                ;; An iovec for each line at 0 and 8, the lines at 16 and 20.
+                (data (i32.const 0) "\10\00\00\00\04\00\00\00\14\00\00\00\04\00\00\00")
+                (data (i32.const 16) "out\nerr\n")
+                (func (export "wapc_init")
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 32)))
+                    (drop (call $fd_write (i32.const 2) (i32.const 8) (i32.const 1) (i32.const 32))))
+                (func (export "__guest_call") (param i32 i32) (result i32)
+                    (i32.const 0)))"#,
+        )
+        .unwrap();
+
+        let (stdout, stderr) = (Captured::default(), Captured::default());
+        let wasi = WasiConfig::default()
+            .stdout(Output::Capture(stdout.clone()))
+            .stderr(Output::Capture(stderr.clone()));
+        let config = ModuleConfig {
+            wasi: Some(wasi),
+            ..Default::default()
+        };
+        crate::Module::builder().config(config).build(&printing)?;
+        assert_eq!(stdout.take(), b"out\n");
+        assert_eq!(stderr.contents(), b"err\n");
+        assert!(stdout.contents().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    error::Error as StdError,
+    io,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use wapc::{ModuleState, WebAssemblyEngineProvider};
+use wasi_common::pipe::WritePipe;
+use wasmtime::{
+    Caller, Config, Engine, InterruptHandle, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Trap, TypedFunc,
+};
+use wasmtime_wasi::{
+    sync::{ambient_authority, stdio, Dir, WasiCtxBuilder},
+    WasiCtx, WasiFile,
+};
+
+use super::{guest_bytes, Exceeded, Provider};
+use crate::{
+    config::{ModuleConfig, Output, WasiConfig},
+    error::Error,
+    limits::{Limits, PAGE_SIZE},
+};
+
+/// What a guest's store holds: the waPC host state, and what the host
+/// enforces and provides on the guest's behalf.
+struct State {
+    host: Arc<ModuleState>,
+    limits: StoreLimits,
+    wasi: Option<WasiCtx>,
+}
+
+/// A waPC engine that compiles guests to native code with wasmtime, or loads
+/// code [crate::aot] compiled ahead of time, and enforces [Limits] on them.
+pub(crate) struct WasmtimeEngine {
+    engine: Engine,
+    module: Module,
+    limits: Limits,
+    wasi: Option<WasiConfig>,
+    watchdog: Option<Watchdog>,
+    guest: Option<Guest>,
+}
+
+struct Guest {
+    store: Store<State>,
+    call: TypedFunc<(i32, i32), i32>,
+    memory: Option<Memory>,
+    interrupt: Arc<InterruptHandle>,
+}
+
+/// The engine every module is compiled with. Fuel metering and interruption
+/// are compiled into the code, so artifacts from [crate::aot] use it too.
+pub(crate) fn engine() -> Engine {
+    let mut config = Config::new();
+    config.consume_fuel(true).interruptable(true);
+    Engine::new(&config).expect("fuel and interruption are always supported")
+}
+
+/// A module compiled for [engine], which instances can share since both are
+/// reference counted.
+#[derive(Clone)]
+pub(crate) struct Compiled {
+    engine: Engine,
+    module: Module,
+}
+
+impl Compiled {
+    pub(crate) fn new(wasm: &[u8]) -> Result<Self, Error> {
+        let engine = engine();
+        let module =
+            Module::new(&engine, wasm).map_err(|e| Error::invalid_module(e.to_string()))?;
+        Ok(Compiled { engine, module })
+    }
+
+    /// Loads code compiled by [crate::aot::compile].
+    ///
+    /// # Safety
+    ///
+    /// wasmtime runs `compiled` as native code without validating it, so it
+    /// must come from a trusted source. The engine version in the artifact
+    /// header only guards against mistakes, anyone can write it.
+    pub(crate) unsafe fn deserialize(compiled: &[u8]) -> Result<Self, Error> {
+        let engine = engine();
+        let module = Module::deserialize(&engine, compiled)
+            .map_err(|e| Error::InvalidArtifact(e.to_string()))?;
+        Ok(Compiled { engine, module })
+    }
+}
+
+impl WasmtimeEngine {
+    pub(crate) fn new(compiled: Compiled, config: &ModuleConfig) -> Self {
+        WasmtimeEngine {
+            engine: compiled.engine,
+            module: compiled.module,
+            limits: config.limits,
+            wasi: config.wasi.clone(),
+            watchdog: config.limits.timeout.map(Watchdog::new),
+            guest: None,
+        }
+    }
+
+    fn instantiate(&self, host: Arc<ModuleState>) -> Result<Guest, Box<dyn StdError>> {
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(max_pages) = self.limits.max_memory_pages {
+            limits = limits.memory_size(max_pages as usize * PAGE_SIZE);
+        }
+        let wasi = self.wasi.as_ref().map(wasi_ctx).transpose()?;
+        let state = State {
+            host,
+            limits: limits.build(),
+            wasi,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        // Initializing gets the same budget as a call, and calls top it back
+        // up. Without a limit, this is as good as unlimited.
+        store.add_fuel(self.limits.fuel.unwrap_or(u64::MAX))?;
+        let interrupt = Arc::new(store.interrupt_handle()?);
+
+        let mut linker = linker(&self.engine)?;
+        if self.wasi.is_some() {
+            wasmtime_wasi::add_to_linker(&mut linker, |state: &mut State| {
+                state
+                    .wasi
+                    .as_mut()
+                    .expect("WASI is only linked when it's configured")
+            })?;
+        }
+        let instance = linker.instantiate(&mut store, &self.module)?;
+        for start in ["_start", "wapc_init"] {
+            if let Ok(init) = instance.get_typed_func::<(), (), _>(&mut store, start) {
+                init.call(&mut store, ())?;
+            }
+        }
+        let call = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "__guest_call")?;
+        let memory = instance.get_memory(&mut store, "memory");
+        Ok(Guest {
+            store,
+            call,
+            memory,
+            interrupt,
+        })
+    }
+
+    /// Starts the guest over in a fresh store, keeping its host state.
+    fn restart(&mut self) -> Result<(), Box<dyn StdError>> {
+        let host = match &self.guest {
+            Some(guest) => guest.store.data().host.clone(),
+            None => return Err("guest restarted before init".into()),
+        };
+        self.guest = Some(self.instantiate(host)?);
+        Ok(())
+    }
+}
+
+impl WebAssemblyEngineProvider for WasmtimeEngine {
+    fn init(&mut self, host: Arc<ModuleState>) -> Result<(), Box<dyn StdError>> {
+        self.guest = Some(self.instantiate(host)?);
+        Ok(())
+    }
+
+    fn call(&mut self, op_length: i32, msg_length: i32) -> Result<i32, Box<dyn StdError>> {
+        let guest = self.guest.as_mut().ok_or("guest called before init")?;
+        if let Some(fuel) = self.limits.fuel {
+            // Calls only ever use fuel, so this tops the store back up to the limit.
+            let remaining = guest.store.consume_fuel(0).unwrap_or(0);
+            guest.store.add_fuel(fuel - remaining)?;
+        }
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.arm(guest.interrupt.clone());
+        }
+        let result = guest.call.call(&mut guest.store, (op_length, msg_length));
+        let out_of_fuel = self.limits.fuel.is_some() && guest.store.consume_fuel(0).is_err();
+        let timed_out = self.watchdog.as_ref().is_some_and(Watchdog::disarm);
+
+        if timed_out {
+            // The guest may have been stopped halfway through changing its
+            // state, and an interrupt that lands just as a call returns stays
+            // pending for the next one, so start over either way.
+            self.restart()?;
+        }
+        match result {
+            Ok(result) => Ok(result),
+            Err(_) if timed_out => Err(Box::new(Exceeded::Timeout(
+                self.limits.timeout.unwrap_or_default(),
+            ))),
+            Err(_) if out_of_fuel => Err(Box::new(Exceeded::Fuel(
+                self.limits.fuel.unwrap_or_default(),
+            ))),
+            Err(trap) => Err(trap.into()),
+        }
+    }
+
+    fn replace(&mut self, bytes: &[u8]) -> Result<(), Box<dyn StdError>> {
+        self.module = Module::new(&self.engine, bytes)?;
+        self.restart()
+    }
+}
+
+impl Provider for WasmtimeEngine {
+    fn memory_size(&self) -> usize {
+        self.guest.as_ref().map_or(0, |guest| {
+            guest
+                .memory
+                .map_or(0, |memory| memory.data_size(&guest.store))
+        })
+    }
+}
+
+fn wasi_ctx(config: &WasiConfig) -> Result<WasiCtx, Box<dyn StdError>> {
+    let mut builder = WasiCtxBuilder::new()
+        .inherit_stdin()
+        .stdout(output(&config.stdout, Box::new(stdio::stdout())))
+        .stderr(output(&config.stderr, Box::new(stdio::stderr())))
+        .args(&config.args)?
+        .envs(&config.env)?;
+    let dirs = config
+        .preopened_dirs
+        .iter()
+        .map(|dir| (dir.to_string_lossy().into_owned(), dir))
+        .chain(
+            config
+                .mapped_dirs
+                .iter()
+                .map(|(guest, host)| (guest.clone(), host)),
+        );
+    for (guest, host) in dirs {
+        let dir = Dir::open_ambient_dir(host, ambient_authority())?;
+        builder = builder.preopened_dir(dir, guest)?;
+    }
+    Ok(builder.build())
+}
+
+/// The file a guest writes `output` to, `inherited` when it goes to the host's stream.
+fn output(output: &Output, inherited: Box<dyn WasiFile>) -> Box<dyn WasiFile> {
+    match output {
+        Output::Inherit => inherited,
+        Output::Discard => Box::new(WritePipe::new(io::sink())),
+        Output::Capture(captured) => Box::new(WritePipe::from_shared(captured.0.clone())),
+    }
+}
+
+/// Interrupts calls that run past the timeout, from a thread that lives as
+/// long as the engine.
+struct Watchdog {
+    timeout: Duration,
+    watch: Arc<(Mutex<Watch>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Watch {
+    /// When the running call is due to finish, and how to stop it if it doesn't.
+    call: Option<(Instant, Arc<InterruptHandle>)>,
+    interrupted: bool,
+    closed: bool,
+}
+
+impl Watchdog {
+    fn new(timeout: Duration) -> Self {
+        let watch = Arc::new((Mutex::new(Watch::default()), Condvar::new()));
+        let thread = {
+            let watch = watch.clone();
+            thread::spawn(move || Self::run(&watch.0, &watch.1))
+        };
+        Watchdog {
+            timeout,
+            watch,
+            thread: Some(thread),
+        }
+    }
+
+    fn arm(&self, interrupt: Arc<InterruptHandle>) {
+        let mut watch = self.lock();
+        watch.call = Some((Instant::now() + self.timeout, interrupt));
+        watch.interrupted = false;
+        self.watch.1.notify_one();
+    }
+
+    /// Stops watching the call, returning whether it was interrupted.
+    fn disarm(&self) -> bool {
+        let mut watch = self.lock();
+        watch.call = None;
+        watch.interrupted
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Watch> {
+        self.watch.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run(watch: &Mutex<Watch>, wake: &Condvar) {
+        let mut watch = watch.lock().unwrap_or_else(PoisonError::into_inner);
+        while !watch.closed {
+            let deadline = watch.call.as_ref().map(|(deadline, _)| *deadline);
+            watch = match deadline {
+                None => wake.wait(watch).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) if Instant::now() >= deadline => {
+                    if let Some((_, interrupt)) = watch.call.take() {
+                        interrupt.interrupt();
+                    }
+                    watch.interrupted = true;
+                    watch
+                }
+                Some(deadline) => {
+                    let wait = deadline.saturating_duration_since(Instant::now());
+                    wake.wait_timeout(watch, wait)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.lock().closed = true;
+        self.watch.1.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The host side of the waPC ABI, the functions guests import from `wapc`.
+fn linker(engine: &Engine) -> Result<Linker<State>, Box<dyn StdError>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        "wapc",
+        "__guest_request",
+        |mut caller: Caller<'_, State>, op_ptr: i32, ptr: i32| {
+            if let Some(invocation) = caller.data().host.get_guest_request() {
+                write(&mut caller, op_ptr, invocation.operation.as_bytes())?;
+                write(&mut caller, ptr, &invocation.msg)?;
+            }
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wapc",
+        "__guest_response",
+        |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+            let response = read(&mut caller, ptr, len)?;
+            caller.data().host.set_guest_response(response);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wapc",
+        "__guest_error",
+        |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+            let error = read_string(&mut caller, ptr, len)?;
+            caller.data().host.set_guest_error(error);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wapc",
+        "__host_call",
+        |mut caller: Caller<'_, State>,
+         bd_ptr: i32,
+         bd_len: i32,
+         ns_ptr: i32,
+         ns_len: i32,
+         op_ptr: i32,
+         op_len: i32,
+         ptr: i32,
+         len: i32| {
+            let binding = read_string(&mut caller, bd_ptr, bd_len)?;
+            let namespace = read_string(&mut caller, ns_ptr, ns_len)?;
+            let operation = read_string(&mut caller, op_ptr, op_len)?;
+            let payload = read(&mut caller, ptr, len)?;
+            caller
+                .data()
+                .host
+                .do_host_call(&binding, &namespace, &operation, &payload)
+                .map_err(|e| Trap::new(e.to_string()))
+        },
+    )?;
+    linker.func_wrap(
+        "wapc",
+        "__host_response",
+        |mut caller: Caller<'_, State>, ptr: i32| {
+            if let Some(response) = caller.data().host.get_host_response() {
+                write(&mut caller, ptr, &response)?;
+            }
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wapc",
+        "__host_response_len",
+        |caller: Caller<'_, State>| {
+            caller
+                .data()
+                .host
+                .get_host_response()
+                .map_or(0, |response| response.len() as i32)
+        },
+    )?;
+    linker.func_wrap(
+        "wapc",
+        "__host_error",
+        |mut caller: Caller<'_, State>, ptr: i32| {
+            if let Some(error) = caller.data().host.get_host_error() {
+                write(&mut caller, ptr, error.as_bytes())?;
+            }
+            Ok(())
+        },
+    )?;
+    linker.func_wrap("wapc", "__host_error_len", |caller: Caller<'_, State>| {
+        caller
+            .data()
+            .host
+            .get_host_error()
+            .map_or(0, |error| error.len() as i32)
+    })?;
+    linker.func_wrap(
+        "wapc",
+        "__console_log",
+        |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+            let message = read_string(&mut caller, ptr, len)?;
+            caller.data().host.do_console_log(&message);
+            Ok(())
+        },
+    )?;
+    Ok(linker)
+}
+
+fn read(caller: &mut Caller<'_, State>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| Trap::new("guest doesn't export its memory"))?;
+    guest_bytes(memory.data(&caller), ptr, len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| Trap::new(format!("{} bytes at {} are out of bounds", len, ptr as u32)))
+}
+
+fn read_string(caller: &mut Caller<'_, State>, ptr: i32, len: i32) -> Result<String, Trap> {
+    let bytes = read(caller, ptr, len)?;
+    String::from_utf8(bytes).map_err(|e| Trap::new(e.to_string()))
+}
+
+fn write(caller: &mut Caller<'_, State>, ptr: i32, bytes: &[u8]) -> Result<(), Trap> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| Trap::new("guest doesn't export its memory"))?;
+    memory
+        .write(caller, ptr as u32 as usize, bytes)
+        .map_err(|e| Trap::new(e.to_string()))
+}
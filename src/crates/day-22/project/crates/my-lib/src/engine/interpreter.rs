@@ -0,0 +1,243 @@
+use std::{error::Error as StdError, fmt, sync::Arc};
+
+use wapc::{ModuleState, WebAssemblyEngineProvider};
+use wasmi::{
+    Externals, FuncInstance, FuncRef, HostError, ImportsBuilder, MemoryRef, ModuleImportResolver,
+    ModuleInstance, ModuleRef, RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind, ValueType,
+};
+
+use super::{guest_bytes, Provider};
+use crate::error::Error;
+
+/// The functions guests import from `wapc`, with how many i32s each takes and
+/// whether it returns one. A function's index here is its wasmi host index.
+const FUNCTIONS: &[(&str, usize, bool)] = &[
+    ("__guest_request", 2, false),
+    ("__guest_response", 2, false),
+    ("__guest_error", 2, false),
+    ("__host_call", 8, true),
+    ("__host_response", 1, false),
+    ("__host_response_len", 0, true),
+    ("__host_error", 1, false),
+    ("__host_error_len", 0, true),
+    ("__console_log", 2, false),
+];
+
+/// A waPC engine that interprets guests with wasmi, for targets where
+/// wasmtime's JIT isn't allowed.
+pub(crate) struct WasmiEngine {
+    module: wasmi::Module,
+    guest: Option<Guest>,
+}
+
+struct Guest {
+    instance: ModuleRef,
+    host: HostFunctions,
+}
+
+struct HostFunctions {
+    state: Arc<ModuleState>,
+    memory: Option<MemoryRef>,
+}
+
+#[derive(Debug)]
+struct HostFailure(String);
+
+impl fmt::Display for HostFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl HostError for HostFailure {}
+
+impl WasmiEngine {
+    pub(crate) fn new(wasm: &[u8]) -> Result<Self, Error> {
+        let module =
+            wasmi::Module::from_buffer(wasm).map_err(|e| Error::invalid_module(e.to_string()))?;
+        Ok(WasmiEngine {
+            module,
+            guest: None,
+        })
+    }
+
+    fn instantiate(&self, state: Arc<ModuleState>) -> Result<Guest, Box<dyn StdError>> {
+        let imports = ImportsBuilder::new().with_resolver("wapc", &Resolver);
+        let instance = ModuleInstance::new(&self.module, &imports)?;
+        let memory = instance
+            .not_started_instance()
+            .export_by_name("memory")
+            .and_then(|export| export.as_memory().cloned());
+        let mut host = HostFunctions { state, memory };
+        let instance = instance.run_start(&mut host)?;
+        for start in ["_start", "wapc_init"] {
+            if instance.export_by_name(start).is_some() {
+                instance.invoke_export(start, &[], &mut host)?;
+            }
+        }
+        Ok(Guest { instance, host })
+    }
+}
+
+impl Provider for WasmiEngine {
+    fn memory_size(&self) -> usize {
+        self.guest
+            .as_ref()
+            .and_then(|guest| guest.host.memory.as_ref())
+            .map_or(0, |memory| memory.with_direct_access(|bytes| bytes.len()))
+    }
+}
+
+impl WebAssemblyEngineProvider for WasmiEngine {
+    fn init(&mut self, host: Arc<ModuleState>) -> Result<(), Box<dyn StdError>> {
+        self.guest = Some(self.instantiate(host)?);
+        Ok(())
+    }
+
+    fn call(&mut self, op_length: i32, msg_length: i32) -> Result<i32, Box<dyn StdError>> {
+        let guest = self.guest.as_mut().ok_or("guest called before init")?;
+        let args = [RuntimeValue::I32(op_length), RuntimeValue::I32(msg_length)];
+        match guest
+            .instance
+            .invoke_export("__guest_call", &args, &mut guest.host)?
+        {
+            Some(RuntimeValue::I32(result)) => Ok(result),
+            _ => Err("__guest_call didn't return an i32".into()),
+        }
+    }
+
+    fn replace(&mut self, bytes: &[u8]) -> Result<(), Box<dyn StdError>> {
+        let state = match &self.guest {
+            Some(guest) => guest.host.state.clone(),
+            None => return Err("guest replaced before init".into()),
+        };
+        self.module = wasmi::Module::from_buffer(bytes)?;
+        self.guest = Some(self.instantiate(state)?);
+        Ok(())
+    }
+}
+
+struct Resolver;
+
+impl ModuleImportResolver for Resolver {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, wasmi::Error> {
+        let index = FUNCTIONS
+            .iter()
+            .position(|(name, _, _)| *name == field_name)
+            .ok_or_else(|| {
+                wasmi::Error::Instantiation(format!("wapc doesn't export {}", field_name))
+            })?;
+        let (_, params, returns) = FUNCTIONS[index];
+        let expected = Signature::new(
+            vec![ValueType::I32; params],
+            returns.then_some(ValueType::I32),
+        );
+        if signature != &expected {
+            return Err(wasmi::Error::Instantiation(format!(
+                "{} is imported with the wrong signature",
+                field_name
+            )));
+        }
+        Ok(FuncInstance::alloc_host(expected, index))
+    }
+}
+
+impl Externals for HostFunctions {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let arg = |n: usize| args.nth_checked::<i32>(n);
+        match FUNCTIONS[index].0 {
+            "__guest_request" => {
+                if let Some(invocation) = self.state.get_guest_request() {
+                    self.write(arg(0)?, invocation.operation.as_bytes())?;
+                    self.write(arg(1)?, &invocation.msg)?;
+                }
+                Ok(None)
+            }
+            "__guest_response" => {
+                let response = self.read(arg(0)?, arg(1)?)?;
+                self.state.set_guest_response(response);
+                Ok(None)
+            }
+            "__guest_error" => {
+                let error = self.read_string(arg(0)?, arg(1)?)?;
+                self.state.set_guest_error(error);
+                Ok(None)
+            }
+            "__host_call" => {
+                let binding = self.read_string(arg(0)?, arg(1)?)?;
+                let namespace = self.read_string(arg(2)?, arg(3)?)?;
+                let operation = self.read_string(arg(4)?, arg(5)?)?;
+                let payload = self.read(arg(6)?, arg(7)?)?;
+                let result = self
+                    .state
+                    .do_host_call(&binding, &namespace, &operation, &payload)
+                    .map_err(|e| trap(e.to_string()))?;
+                Ok(Some(RuntimeValue::I32(result)))
+            }
+            "__host_response" => {
+                if let Some(response) = self.state.get_host_response() {
+                    self.write(arg(0)?, &response)?;
+                }
+                Ok(None)
+            }
+            "__host_response_len" => {
+                let len = self.state.get_host_response().map_or(0, |r| r.len());
+                Ok(Some(RuntimeValue::I32(len as i32)))
+            }
+            "__host_error" => {
+                if let Some(error) = self.state.get_host_error() {
+                    self.write(arg(0)?, error.as_bytes())?;
+                }
+                Ok(None)
+            }
+            "__host_error_len" => {
+                let len = self.state.get_host_error().map_or(0, |e| e.len());
+                Ok(Some(RuntimeValue::I32(len as i32)))
+            }
+            "__console_log" => {
+                let message = self.read_string(arg(0)?, arg(1)?)?;
+                self.state.do_console_log(&message);
+                Ok(None)
+            }
+            name => Err(trap(format!("no host function {}", name))),
+        }
+    }
+}
+
+impl HostFunctions {
+    fn memory(&self) -> Result<&MemoryRef, Trap> {
+        self.memory
+            .as_ref()
+            .ok_or_else(|| trap("guest doesn't export its memory".to_owned()))
+    }
+
+    fn read(&self, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+        self.memory()?.with_direct_access(|memory| {
+            guest_bytes(memory, ptr, len)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| trap(format!("{} bytes at {} are out of bounds", len, ptr as u32)))
+        })
+    }
+
+    fn read_string(&self, ptr: i32, len: i32) -> Result<String, Trap> {
+        String::from_utf8(self.read(ptr, len)?).map_err(|e| trap(e.to_string()))
+    }
+
+    fn write(&self, ptr: i32, bytes: &[u8]) -> Result<(), Trap> {
+        self.memory()?
+            .set(ptr as u32, bytes)
+            .map_err(|e| trap(e.to_string()))
+    }
+}
+
+fn trap(message: String) -> Trap {
+    Trap::new(TrapKind::Host(Box::new(HostFailure(message))))
+}
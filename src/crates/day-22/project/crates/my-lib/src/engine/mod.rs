@@ -0,0 +1,392 @@
+use std::{
+    error::Error as StdError,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
+
+use wapc::{ModuleState, WebAssemblyEngineProvider};
+
+use crate::{
+    aot::Artifact,
+    config::ModuleConfig,
+    error::{Error, HostCallFailure},
+};
+
+#[cfg(feature = "wasmtime")]
+pub(crate) mod compiler;
+#[cfg(feature = "wasmi")]
+mod interpreter;
+
+#[cfg(not(any(feature = "wasmtime", feature = "wasmi")))]
+compile_error!("my-lib needs an engine, enable the wasmtime or wasmi feature");
+
+/// The WebAssembly engine a [crate::Module] runs on. Each engine is behind the
+/// cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    /// Compiles guests to native code. The default when it's enabled.
+    #[cfg(feature = "wasmtime")]
+    Wasmtime,
+    /// Interprets guests, for targets that don't allow JIT compilation.
+    #[cfg(feature = "wasmi")]
+    Wasmi,
+}
+
+impl EngineKind {
+    /// Every engine enabled in this build.
+    pub fn available() -> Vec<EngineKind> {
+        vec![
+            #[cfg(feature = "wasmtime")]
+            EngineKind::Wasmtime,
+            #[cfg(feature = "wasmi")]
+            EngineKind::Wasmi,
+        ]
+    }
+
+    /// Whether the engine enforces [crate::limits::Limits] while guests run.
+    /// Others can't stop a call partway, so they reject timeouts and fuel, and
+    /// only check memory against what modules declare.
+    pub fn enforces_limits(self) -> bool {
+        match self {
+            #[cfg(feature = "wasmtime")]
+            EngineKind::Wasmtime => true,
+            #[cfg(feature = "wasmi")]
+            EngineKind::Wasmi => false,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "wasmtime")]
+            EngineKind::Wasmtime => "wasmtime",
+            #[cfg(feature = "wasmi")]
+            EngineKind::Wasmi => "wasmi",
+        }
+    }
+}
+
+impl Default for EngineKind {
+    fn default() -> Self {
+        EngineKind::available()[0]
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let available = EngineKind::available();
+        for engine in &available {
+            if engine.name() == s {
+                return Ok(*engine);
+            }
+        }
+        let names: Vec<_> = available.iter().map(|engine| engine.name()).collect();
+        Err(format!(
+            "Unknown engine '{}', this build supports {}",
+            s,
+            names.join(", ")
+        ))
+    }
+}
+
+/// A waPC engine that can also say how big its guest's memory is.
+pub(crate) trait Provider: WebAssemblyEngineProvider {
+    /// The size of the guest's linear memory in bytes, 0 if it has none.
+    fn memory_size(&self) -> usize;
+}
+
+/// Code compiled once and shared by every module built with it, which a
+/// [crate::pool::ModulePool] uses so its instances don't each compile the same
+/// module. Only wasmtime compiles modules; the first build compiles, and later
+/// builds must be of the same bytes.
+#[derive(Clone, Default)]
+pub(crate) struct Shared {
+    #[cfg(feature = "wasmtime")]
+    compiled: Arc<Mutex<Option<compiler::Compiled>>>,
+}
+
+#[cfg(feature = "wasmtime")]
+impl Shared {
+    fn get_or_compile(
+        &self,
+        compile: impl FnOnce() -> Result<compiler::Compiled, Error>,
+    ) -> Result<compiler::Compiled, Error> {
+        let mut shared = self.compiled.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(compiled) = &*shared {
+            return Ok(compiled.clone());
+        }
+        let compiled = compile()?;
+        *shared = Some(compiled.clone());
+        Ok(compiled)
+    }
+}
+
+/// Creates the waPC engine for `bytes`, which may be wasm, or a precompiled
+/// artifact when `precompiled` says the caller opted in to loading one.
+pub(crate) fn provider(
+    kind: EngineKind,
+    bytes: &[u8],
+    config: &ModuleConfig,
+    precompiled: bool,
+    shared: Option<&Shared>,
+) -> Result<Box<dyn Provider>, Error> {
+    let artifact = Artifact::parse(bytes)?;
+    if artifact.is_some() && !precompiled {
+        return Err(Error::InvalidArtifact(
+            "precompiled modules must be loaded with from_precompiled".to_owned(),
+        ));
+    }
+    match kind {
+        #[cfg(feature = "wasmtime")]
+        EngineKind::Wasmtime => {
+            if artifact.is_some() && config.wasi.is_some() {
+                return Err(Error::EngineUnsupported(
+                    kind.to_string(),
+                    "WASI for precompiled modules".to_owned(),
+                ));
+            }
+            let compile = || match &artifact {
+                // Safety: the caller opted in to running this artifact's code, which
+                // is only done for from_precompiled and the compile cache.
+                Some(artifact) => unsafe { compiler::Compiled::deserialize(artifact.compiled) },
+                None => compiler::Compiled::new(bytes),
+            };
+            let compiled = match shared {
+                Some(shared) => shared.get_or_compile(compile)?,
+                None => compile()?,
+            };
+            Ok(Box::new(compiler::WasmtimeEngine::new(compiled, config)))
+        }
+        #[cfg(feature = "wasmi")]
+        EngineKind::Wasmi => {
+            if config.wasi.is_some() {
+                return Err(Error::EngineUnsupported(
+                    kind.to_string(),
+                    "WASI".to_owned(),
+                ));
+            }
+            if config.limits.timeout.is_some() || config.limits.fuel.is_some() {
+                return Err(Error::EngineUnsupported(
+                    kind.to_string(),
+                    "timeouts or fuel".to_owned(),
+                ));
+            }
+            // An interpreter has no use for compiled code, only the wasm it
+            // came from, so there's nothing to share either.
+            let _ = shared;
+            let wasm = artifact.map_or(bytes, |artifact| artifact.wasm);
+            Ok(Box::new(interpreter::WasmiEngine::new(wasm)?))
+        }
+    }
+}
+
+/// A limit that stopped a guest call, which engines that enforce limits
+/// return as the call's error.
+#[cfg_attr(not(feature = "wasmtime"), allow(dead_code))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Exceeded {
+    Fuel(u64),
+    Timeout(Duration),
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceeded::Fuel(fuel) => write!(f, "used up its {} units of fuel", fuel),
+            Exceeded::Timeout(timeout) => write!(f, "did not finish within {:?}", timeout),
+        }
+    }
+}
+
+impl StdError for Exceeded {}
+
+/// Why the last guest call failed, beyond what waPC reports: it turns traps,
+/// guest errors and failed host calls alike into a `GuestCallFailure` string.
+#[derive(Default)]
+pub(crate) struct Failures {
+    trap: Mutex<Option<String>>,
+    exceeded: Mutex<Option<Exceeded>>,
+    host_calls: Mutex<Vec<HostCallFailure>>,
+}
+
+impl Failures {
+    pub(crate) fn clear(&self) {
+        self.take_trap();
+        self.take_exceeded();
+        self.lock_host_calls().clear();
+    }
+
+    fn trapped(&self, message: String) {
+        *self.trap.lock().unwrap_or_else(PoisonError::into_inner) = Some(message);
+    }
+
+    fn exceeded(&self, exceeded: Exceeded) {
+        *self.exceeded.lock().unwrap_or_else(PoisonError::into_inner) = Some(exceeded);
+    }
+
+    pub(crate) fn host_call_failed(&self, failure: HostCallFailure) {
+        self.lock_host_calls().push(failure);
+    }
+
+    pub(crate) fn take_trap(&self) -> Option<String> {
+        self.trap
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    pub(crate) fn take_exceeded(&self) -> Option<Exceeded> {
+        self.exceeded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// The failed host call behind `guest_error`, if any.
+    ///
+    /// Guests may handle a failed host call and go on to fail for another
+    /// reason, so a failure only counts when the guest passed its message on.
+    pub(crate) fn take_host_call(&self, guest_error: &str) -> Option<HostCallFailure> {
+        std::mem::take(&mut *self.lock_host_calls())
+            .into_iter()
+            .rev()
+            .find(|failure| !failure.message.is_empty() && guest_error.contains(&failure.message))
+    }
+
+    fn lock_host_calls(&self) -> MutexGuard<'_, Vec<HostCallFailure>> {
+        self.host_calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Wraps an engine to note when a call traps or hits a limit, which are the
+/// only times an engine's `call` fails rather than returning the guest's status.
+pub(crate) struct Tracked {
+    engine: Box<dyn Provider>,
+    failures: Arc<Failures>,
+    /// The guest's memory size as of the last init or call.
+    memory: Arc<AtomicUsize>,
+}
+
+impl Tracked {
+    pub(crate) fn new(
+        engine: Box<dyn Provider>,
+        failures: Arc<Failures>,
+        memory: Arc<AtomicUsize>,
+    ) -> Self {
+        Tracked {
+            engine,
+            failures,
+            memory,
+        }
+    }
+
+    fn measure_memory(&self) {
+        self.memory
+            .store(self.engine.memory_size(), Ordering::Relaxed);
+    }
+}
+
+impl WebAssemblyEngineProvider for Tracked {
+    fn init(&mut self, host: Arc<ModuleState>) -> Result<(), Box<dyn StdError>> {
+        self.engine.init(host)?;
+        self.measure_memory();
+        Ok(())
+    }
+
+    fn call(&mut self, op_length: i32, msg_length: i32) -> Result<i32, Box<dyn StdError>> {
+        let result = self.engine.call(op_length, msg_length).inspect_err(|e| {
+            match e.downcast_ref::<Exceeded>() {
+                Some(exceeded) => self.failures.exceeded(*exceeded),
+                None => self.failures.trapped(e.to_string()),
+            }
+        });
+        self.measure_memory();
+        result
+    }
+
+    fn replace(&mut self, bytes: &[u8]) -> Result<(), Box<dyn StdError>> {
+        self.engine.replace(bytes)?;
+        self.measure_memory();
+        Ok(())
+    }
+}
+
+/// The `len` bytes at `ptr` in a guest's `memory`, or `None` when the guest
+/// passed a negative length or a range that runs past the end of its memory.
+/// Both come straight from the guest, so they're checked before anything is
+/// allocated for them.
+pub(crate) fn guest_bytes(memory: &[u8], ptr: i32, len: i32) -> Option<&[u8]> {
+    // Pointers are unsigned in wasm, lengths are never negative.
+    let start = ptr as u32 as usize;
+    let len = usize::try_from(len).ok()?;
+    memory.get(start..start.checked_add(len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(operation: &str, message: &str) -> HostCallFailure {
+        HostCallFailure {
+            binding: "myBinding".to_owned(),
+            namespace: "sample".to_owned(),
+            operation: operation.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn traps_on_out_of_bounds_host_call_arguments() -> Result<(), Error> {
+        for len in [-1, i32::MAX] {
+            // A guest that logs `len` bytes from the start of its one-page memory.
+            let logging = wat::parse_str(format!(
+                r#"(module
+                    (import "wapc" "__console_log" (func $log (param i32 i32)))
+                    (memory (export "memory") 1)
+                    (func (export "__guest_call") (param i32 i32) (result i32)
+                        (call $log (i32.const 0) (i32.const {}))
+                        (i32.const 1)))"#,
+                len
+            ))
+            .unwrap();
+            for engine in EngineKind::available() {
+                let result = crate::Module::with_engine(engine, &logging)?.run("log", b"");
+                assert!(
+                    matches!(result, Err(Error::Trap { .. })),
+                    "{:?} for {} bytes on {}",
+                    result,
+                    len,
+                    engine
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn blames_only_host_calls_the_guest_passed_on() {
+        let failures = Failures::default();
+        failures.host_call_failed(failure("first", "disk full"));
+        failures.host_call_failed(failure("second", "ignored by the guest"));
+        let blamed = failures.take_host_call("Host error: disk full");
+        assert_eq!(blamed.map(|f| f.operation), Some("first".to_owned()));
+
+        failures.host_call_failed(failure("third", "ignored by the guest"));
+        assert!(failures.take_host_call("bad input").is_none());
+        assert!(failures.lock_host_calls().is_empty());
+    }
+}
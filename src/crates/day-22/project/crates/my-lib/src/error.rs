@@ -1,9 +1,188 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, time::Duration};
 
+/// Everything that can go wrong loading or calling a module. Errors from a
+/// call name the module, by its file path when it was loaded from one, and
+/// the operation that was called.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error(transparent)]
     WapcError(#[from] wapc::errors::Error),
+    #[error("{module}: {operation} failed: {message}")]
+    GuestError {
+        module: String,
+        operation: String,
+        message: String,
+    },
+    #[error("{module}: {operation} trapped: {backtrace}")]
+    Trap {
+        module: String,
+        operation: String,
+        /// The engine's description of the trap, with the wasm backtrace when it has one.
+        backtrace: String,
+    },
+    #[error("{module} has no operation named {operation}")]
+    UnknownOperation { module: String, operation: String },
+    #[error("{module}: {operation} failed calling {host_call}")]
+    HostCallFailed {
+        module: String,
+        operation: String,
+        host_call: Box<HostCallFailure>,
+    },
     #[error("Could not read file {0}: {1}")]
     FileNotReadable(PathBuf, String),
+    #[error("Could not write file {0}: {1}")]
+    FileNotWritable(PathBuf, String),
+    #[error("Could not watch {0}: {1}")]
+    WatchFailed(PathBuf, String),
+    #[error("No host handler registered for binding={0}, namespace={1}, operation={2}")]
+    NoHandler(String, String, String),
+    #[error("{0} may not call binding={1}, namespace={2}, operation={3}")]
+    PermissionDenied(String, String, String, String),
+    #[error("Invalid permissions: {0}")]
+    InvalidPermissions(String),
+    #[error("Host handler for {0} failed: {1}")]
+    HandlerFailed(String, String),
+    #[error("Unexpected host call {0}")]
+    UnexpectedHostCall(String),
+    #[error("Invalid host call recording: {0}")]
+    InvalidRecording(String),
+    #[error("{0} recorded host calls were never made, starting with {1}")]
+    UnusedRecording(usize, String),
+    #[error("{0}")]
+    RecordedError(String),
+    #[error("Could not encode payload: {0}")]
+    EncodeFailed(String),
+    #[error("Could not decode payload: {0}")]
+    DecodeFailed(String),
+    #[error("All {0} module instances are busy")]
+    PoolExhausted(usize),
+    #[error("Module instance {0} failed: {1}")]
+    InstanceFailed(usize, Box<Error>),
+    #[error("Module instance has shut down")]
+    ModuleClosed,
+    #[error("Invalid WebAssembly module{}: {message}", .module.as_ref().map(|module| format!(" {}", module)).unwrap_or_default())]
+    InvalidModule {
+        /// The module's name, when it was known where the error was found.
+        module: Option<String>,
+        message: String,
+    },
+    #[error("Invalid precompiled module: {0}")]
+    InvalidArtifact(String),
+    #[error("The {0} engine doesn't support {1}")]
+    EngineUnsupported(String, String),
+    #[error("Module is not trusted: {0}")]
+    UntrustedModule(String),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid WIDL interface: {0}")]
+    InvalidInterface(String),
+    #[error("Invalid input for {0}: {1}")]
+    InvalidInput(String, String),
+    #[error("{0} did not finish within {1:?}")]
+    Timeout(String, Duration),
+    #[error("{0} used up its {1} units of fuel")]
+    OutOfFuel(String, u64),
+    #[error("Module declares {0} pages of memory, over the limit of {1}")]
+    MemoryLimit(u64, u32),
+}
+
+/// The host call a guest was making when it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostCallFailure {
+    pub binding: String,
+    pub namespace: String,
+    pub operation: String,
+    pub message: String,
+}
+
+impl fmt::Display for HostCallFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "binding={}, namespace={}, operation={}: {}",
+            self.binding, self.namespace, self.operation, self.message
+        )
+    }
+}
+
+impl Error {
+    pub(crate) fn invalid_module<T: Into<String>>(message: T) -> Self {
+        Error::InvalidModule {
+            module: None,
+            message: message.into(),
+        }
+    }
+
+    /// Names the module an [Error::InvalidModule] was found in.
+    pub(crate) fn in_module(self, name: &str) -> Self {
+        match self {
+            Error::InvalidModule {
+                module: None,
+                message,
+            } => Error::InvalidModule {
+                module: Some(name.to_owned()),
+                message,
+            },
+            e => e,
+        }
+    }
+
+    /// A stable, machine-readable identifier for the kind of error, which
+    /// won't change when its message does.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::WapcError(_) => "wapc_error",
+            Error::GuestError { .. } => "guest_error",
+            Error::Trap { .. } => "trap",
+            Error::UnknownOperation { .. } => "unknown_operation",
+            Error::HostCallFailed { .. } => "host_call_failed",
+            Error::FileNotReadable(..) => "file_not_readable",
+            Error::FileNotWritable(..) => "file_not_writable",
+            Error::WatchFailed(..) => "watch_failed",
+            Error::NoHandler(..) => "no_handler",
+            Error::PermissionDenied(..) => "permission_denied",
+            Error::InvalidPermissions(_) => "invalid_permissions",
+            Error::HandlerFailed(..) => "handler_failed",
+            Error::UnexpectedHostCall(_) => "unexpected_host_call",
+            Error::InvalidRecording(_) => "invalid_recording",
+            Error::UnusedRecording(..) => "unused_recording",
+            Error::RecordedError(_) => "recorded_error",
+            Error::EncodeFailed(_) => "encode_failed",
+            Error::DecodeFailed(_) => "decode_failed",
+            Error::PoolExhausted(_) => "pool_exhausted",
+            Error::InstanceFailed(_, e) => e.code(),
+            Error::ModuleClosed => "module_closed",
+            Error::InvalidModule { .. } => "invalid_module",
+            Error::InvalidArtifact(_) => "invalid_artifact",
+            Error::EngineUnsupported(..) => "engine_unsupported",
+            Error::UntrustedModule(_) => "untrusted_module",
+            Error::InvalidKey(_) => "invalid_key",
+            Error::InvalidInterface(_) => "invalid_interface",
+            Error::InvalidInput(..) => "invalid_input",
+            Error::Timeout(..) => "timeout",
+            Error::OutOfFuel(..) => "out_of_fuel",
+            Error::MemoryLimit(..) => "memory_limit",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_cross_threads() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Error>();
+    }
+
+    #[test]
+    fn names_the_module() {
+        let error = Error::invalid_module("bad magic").in_module("blog.wasm");
+        assert_eq!(
+            error.to_string(),
+            "Invalid WebAssembly module blog.wasm: bad magic"
+        );
+        assert_eq!(error.code(), "invalid_module");
+    }
 }
@@ -0,0 +1,30 @@
+//! Guests written in wat for tests that need more than tests/test.wasm does.
+
+/// A guest whose `__guest_call` passes its payload to the host with
+/// `host_call("db", "", "get", ..)` and responds with whatever the host returned.
+pub(crate) fn forwarding() -> Vec<u8> {
+    wat::parse_str(
+        r#"(module
+            (import "wapc" "__guest_request" (func $guest_request (param i32 i32)))
+            (import "wapc" "__host_call"
+                (func $host_call (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+            (import "wapc" "__host_response_len" (func $host_response_len (result i32)))
+            (import "wapc" "__host_response" (func $host_response (param i32)))
+            (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+            (memory (export "memory") 1)
+            ;; The binding "db" at 0 and the operation "get" at 2, the namespace is empty.
+            (data (i32.const 0) "dbget")
+            (func (export "__guest_call") (param $op_len i32) (param $len i32) (result i32)
+                ;; The operation name lands at 16 and the payload at 64.
+                (call $guest_request (i32.const 16) (i32.const 64))
+                (drop (call $host_call
+                    (i32.const 0) (i32.const 2)
+                    (i32.const 0) (i32.const 0)
+                    (i32.const 2) (i32.const 3)
+                    (i32.const 64) (local.get $len)))
+                (call $host_response (i32.const 1024))
+                (call $guest_response (i32.const 1024) (call $host_response_len))
+                (i32.const 1)))"#,
+    )
+    .unwrap()
+}
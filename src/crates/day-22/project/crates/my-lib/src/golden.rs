@@ -0,0 +1,352 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::{error::Error, ModuleBuilder};
+
+const INPUT: &str = ".input.json";
+const OUTPUT: &str = ".output.json";
+
+/// One golden-file test case: invoke `operation` on `module` with the JSON in
+/// `input` and expect the JSON in `expected` back.
+///
+/// Fixtures for `blog.wasm` sit next to it as
+/// `blog.<operation>[.<case>].input.json` and `...output.json`.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub module: PathBuf,
+    pub operation: String,
+    pub case: Option<String>,
+    pub input: PathBuf,
+    pub expected: PathBuf,
+}
+
+impl fmt::Display for Fixture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let module = self.module.file_name().unwrap_or_default();
+        write!(f, "{} {}", module.to_string_lossy(), self.operation)?;
+        if let Some(case) = &self.case {
+            write!(f, " ({})", case)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a fixture fared.
+#[derive(Debug)]
+pub enum Outcome {
+    Passed,
+    /// The output didn't match, for the reasons listed.
+    Failed(Vec<Difference>),
+    /// The expected output was (re)written from the actual output.
+    Blessed,
+    /// The module couldn't be loaded or the call failed.
+    Errored(String),
+}
+
+/// Where an actual output strays from the expected one. `None` means the value
+/// is missing on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => {
+                write!(f, "{}: expected {}, got {}", self.path, expected, actual)
+            }
+            (Some(expected), None) => write!(f, "{}: missing, expected {}", self.path, expected),
+            (None, Some(actual)) => write!(f, "{}: unexpected {}", self.path, actual),
+            (None, None) => write!(f, "{}", self.path),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub results: Vec<(Fixture, Outcome)>,
+}
+
+impl Report {
+    /// Whether every fixture passed or was blessed.
+    pub fn succeeded(&self) -> bool {
+        self.failures() == 0
+    }
+
+    pub fn failures(&self) -> usize {
+        let mut failures = 0;
+        for (_, outcome) in &self.results {
+            if matches!(outcome, Outcome::Failed(_) | Outcome::Errored(_)) {
+                failures += 1;
+            }
+        }
+        failures
+    }
+}
+
+/// Finds every fixture for the wasm files in `dir`, sorted by path.
+pub fn discover<T: AsRef<Path>>(dir: T) -> Result<Vec<Fixture>, Error> {
+    let dir = dir.as_ref();
+    let mut paths = Vec::new();
+    let entries =
+        fs::read_dir(dir).map_err(|e| Error::FileNotReadable(dir.to_path_buf(), e.to_string()))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::FileNotReadable(dir.to_path_buf(), e.to_string()))?;
+        paths.push(entry.path());
+    }
+    paths.sort();
+
+    let modules: Vec<(&PathBuf, &str)> = paths
+        .iter()
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("wasm"))
+        .filter_map(|path| Some((path, path.file_stem()?.to_str()?)))
+        .collect();
+    let stems: Vec<&str> = modules.iter().map(|(_, stem)| *stem).collect();
+
+    let mut fixtures = Vec::new();
+    for (module, stem) in &modules {
+        for input in &paths {
+            let name = match input.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let (operation, case) = match parse_input(name, &stems) {
+                Some((owner, operation, case)) if owner == *stem => (operation, case),
+                _ => continue,
+            };
+            let rest = name.strip_suffix(INPUT).unwrap_or(name);
+            fixtures.push(Fixture {
+                module: (*module).clone(),
+                operation: operation.to_owned(),
+                case: case.map(str::to_owned),
+                input: input.clone(),
+                expected: dir.join(format!("{}{}", rest, OUTPUT)),
+            });
+        }
+    }
+    Ok(fixtures)
+}
+
+/// Splits an input's file name, `<stem>.<operation>[.<case>].input.json`,
+/// into the stem of the module it belongs to, the operation and the case.
+/// Stems may contain dots, so the longest of `stems` that fits wins.
+fn parse_input<'a>(
+    name: &'a str,
+    stems: &[&'a str],
+) -> Option<(&'a str, &'a str, Option<&'a str>)> {
+    let rest = name.strip_suffix(INPUT)?;
+    stems
+        .iter()
+        .filter_map(|stem| {
+            let mut parts = rest.strip_prefix(stem)?.strip_prefix('.')?.split('.');
+            let operation = parts.next().filter(|part| !part.is_empty())?;
+            let case = parts.next();
+            if case == Some("") || parts.next().is_some() {
+                return None;
+            }
+            Some((*stem, operation, case))
+        })
+        .max_by_key(|(stem, ..)| stem.len())
+}
+
+/// Runs every fixture in `dir`, loading each module once. With `bless`, expected
+/// outputs are overwritten with whatever the module returns.
+pub fn run<T: AsRef<Path>>(builder: &ModuleBuilder, dir: T, bless: bool) -> Result<Report, Error> {
+    let mut report = Report::default();
+    let mut loaded = None;
+    for fixture in discover(dir)? {
+        let module = match &loaded {
+            Some((path, module)) if path == &fixture.module => module,
+            _ => {
+                let module = builder
+                    .clone()
+                    .from_file(&fixture.module)
+                    .map_err(|e| e.to_string());
+                &loaded.insert((fixture.module.clone(), module)).1
+            }
+        };
+        let outcome = match module {
+            Ok(module) => check(module, &fixture, bless),
+            Err(e) => Outcome::Errored(e.clone()),
+        };
+        report.results.push((fixture, outcome));
+    }
+    Ok(report)
+}
+
+fn check(module: &crate::Module, fixture: &Fixture, bless: bool) -> Outcome {
+    let result = read(&fixture.input)
+        .and_then(|input| module.invoke::<_, Value>(&fixture.operation, &input));
+    let actual = match result {
+        Ok(actual) => actual,
+        Err(e) => return Outcome::Errored(e.to_string()),
+    };
+
+    if bless {
+        return match write(&fixture.expected, &actual) {
+            Ok(()) => Outcome::Blessed,
+            Err(e) => Outcome::Errored(e.to_string()),
+        };
+    }
+    if !fixture.expected.exists() {
+        return Outcome::Errored(format!(
+            "{} doesn't exist, bless the fixture to create it",
+            fixture.expected.display()
+        ));
+    }
+    match read(&fixture.expected) {
+        Ok(expected) => {
+            let differences = diff(&expected, &actual);
+            if differences.is_empty() {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(differences)
+            }
+        }
+        Err(e) => Outcome::Errored(e.to_string()),
+    }
+}
+
+/// Compares two JSON values structurally, so key order and formatting don't
+/// matter and each mismatch is reported at its own path.
+pub fn diff(expected: &Value, actual: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_at("output", expected, actual, &mut differences);
+    differences
+}
+
+fn diff_at(path: &str, expected: &Value, actual: &Value, differences: &mut Vec<Difference>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected) in expected {
+                let path = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(actual) => diff_at(&path, expected, actual, differences),
+                    None => differences.push(Difference {
+                        path,
+                        expected: Some(expected.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+            for (key, actual) in actual {
+                if !expected.contains_key(key) {
+                    differences.push(Difference {
+                        path: format!("{}.{}", path, key),
+                        expected: None,
+                        actual: Some(actual.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for i in 0..expected.len().max(actual.len()) {
+                let path = format!("{}[{}]", path, i);
+                match (expected.get(i), actual.get(i)) {
+                    (Some(expected), Some(actual)) => diff_at(&path, expected, actual, differences),
+                    (expected, actual) => differences.push(Difference {
+                        path,
+                        expected: expected.cloned(),
+                        actual: actual.cloned(),
+                    }),
+                }
+            }
+        }
+        _ if expected != actual => differences.push(Difference {
+            path: path.to_owned(),
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        }),
+        _ => {}
+    }
+}
+
+fn read(path: &Path) -> Result<Value, Error> {
+    let json =
+        fs::read(path).map_err(|e| Error::FileNotReadable(path.to_path_buf(), e.to_string()))?;
+    serde_json::from_slice(&json).map_err(|e| Error::DecodeFailed(e.to_string()))
+}
+
+fn write(path: &Path, value: &Value) -> Result<(), Error> {
+    let mut json =
+        serde_json::to_vec_pretty(value).map_err(|e| Error::EncodeFailed(e.to_string()))?;
+    json.push(b'\n');
+    fs::write(path, json).map_err(|e| Error::FileNotWritable(path.to_path_buf(), e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diffs_values_structurally() {
+        let expected = json!({ "title": "Hello", "tags": ["a", "b"], "draft": false });
+        let actual = json!({ "tags": ["a"], "draft": false, "title": "Hi", "author": null });
+
+        let differences: Vec<String> = diff(&expected, &actual)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            differences,
+            vec![
+                "output.tags[1]: missing, expected \"b\"",
+                "output.title: expected \"Hello\", got \"Hi\"",
+                "output.author: unexpected null",
+            ]
+        );
+        assert!(diff(&expected, &expected).is_empty());
+    }
+
+    #[test]
+    fn runs_fixtures() -> Result<(), Error> {
+        let report = run(&crate::Module::builder(), "./tests", false)?;
+        assert_eq!(report.results.len(), 1);
+        assert!(matches!(report.results[0].1, Outcome::Passed));
+        Ok(())
+    }
+
+    #[test]
+    fn discovers_fixtures_next_to_modules() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("my-lib-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "test.wasm",
+            "test.hello.input.json",
+            "test.hello.empty.input.json",
+            "test.hello.output.json",
+            "other.hello.input.json",
+            // Belongs to test.v2.wasm, not an operation "v2" of test.wasm.
+            "test.v2.wasm",
+            "test.v2.hello.input.json",
+            // Too many parts to be a fixture of either.
+            "test.v2.hello.empty.extra.input.json",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let fixtures = discover(&dir)?;
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<String> = fixtures.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            names,
+            vec![
+                "test.v2.wasm hello",
+                "test.wasm hello (empty)",
+                "test.wasm hello"
+            ]
+        );
+        assert_eq!(fixtures[0].expected, dir.join("test.v2.hello.output.json"));
+        assert_eq!(fixtures[2].expected, dir.join("test.hello.output.json"));
+        Ok(())
+    }
+}
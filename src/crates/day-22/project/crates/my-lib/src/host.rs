@@ -0,0 +1,220 @@
+#[cfg(feature = "async")]
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use log::Level;
+
+use crate::error::Error;
+
+/// The result type host-call handlers return to the guest.
+pub type HostResult = Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>;
+
+/// The binding and namespace a guest logs through, with the level as the
+/// operation and the message as the payload. Every module is served it.
+pub const LOG_BINDING: &str = "wapc";
+pub const LOG_NAMESPACE: &str = "log";
+
+type Handler = Arc<dyn Fn(&[u8]) -> HostResult + Send + Sync>;
+type Fallback = Arc<dyn Fn(&str, &str, &str, &[u8]) -> HostResult + Send + Sync>;
+
+/// A registry of handlers that serve the guest's `host_call`s, keyed by
+/// binding, namespace, and operation.
+#[derive(Clone, Default)]
+pub struct HostHandlers {
+    handlers: HashMap<(String, String, String), Handler>,
+    fallback: Option<Fallback>,
+}
+
+impl HostHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for calls to `binding`/`namespace`/`operation`,
+    /// replacing any handler previously registered for the same key.
+    pub fn register<F>(&mut self, binding: &str, namespace: &str, operation: &str, handler: F)
+    where
+        F: Fn(&[u8]) -> HostResult + Send + Sync + 'static,
+    {
+        self.handlers.insert(
+            (
+                binding.to_owned(),
+                namespace.to_owned(),
+                operation.to_owned(),
+            ),
+            Arc::new(handler),
+        );
+    }
+
+    /// Registers an async handler. The guest blocks until the returned future
+    /// resolves on the tokio runtime that was current at registration, so the
+    /// module may be called from any thread, such as a [crate::pool::ModulePool]
+    /// worker. Handlers registered outside a runtime use the caller's.
+    ///
+    /// A thread that is running async tasks can't block on one, so calls made
+    /// from such a thread fail rather than resolving the handler.
+    #[cfg(feature = "async")]
+    pub fn register_async<F, Fut>(
+        &mut self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        handler: F,
+    ) where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = HostResult> + Send + 'static,
+    {
+        let registered = tokio::runtime::Handle::try_current().ok();
+        self.register(binding, namespace, operation, move |payload| {
+            let runtime = match &registered {
+                Some(runtime) => runtime.clone(),
+                None => tokio::runtime::Handle::try_current()?,
+            };
+            // Tokio panics before polling the future if this thread is driving
+            // async tasks, which is the only panic turned into an error here.
+            let started = Cell::new(false);
+            let call = async {
+                started.set(true);
+                handler(payload.to_vec()).await
+            };
+            match panic::catch_unwind(AssertUnwindSafe(|| runtime.block_on(call))) {
+                Ok(result) => result,
+                Err(_) if !started.get() => {
+                    Err("async host calls can't block a thread that runs async tasks".into())
+                }
+                Err(panic) => panic::resume_unwind(panic),
+            }
+        });
+    }
+
+    /// Serves the calls no registered handler matches, given the binding,
+    /// namespace, and operation along with the payload.
+    pub fn register_fallback<F>(&mut self, handler: F)
+    where
+        F: Fn(&str, &str, &str, &[u8]) -> HostResult + Send + Sync + 'static,
+    {
+        self.fallback = Some(Arc::new(handler));
+    }
+
+    pub fn dispatch(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let key = (
+            binding.to_owned(),
+            namespace.to_owned(),
+            operation.to_owned(),
+        );
+        let result = match (self.handlers.get(&key), &self.fallback) {
+            (Some(handler), _) => handler(payload),
+            (None, Some(fallback)) => fallback(binding, namespace, operation, payload),
+            (None, None) => {
+                return Err(Error::NoHandler(
+                    binding.to_owned(),
+                    namespace.to_owned(),
+                    operation.to_owned(),
+                ))
+            }
+        };
+        result.map_err(|e| Error::HandlerFailed(operation.to_owned(), e.to_string()))
+    }
+}
+
+/// Logs a guest's message at the level named by `operation`, with the module's
+/// name as the target so guests can be filtered like any other log source.
+pub(crate) fn log(module: &str, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let level = Level::from_str(operation).map_err(|_| {
+        Error::NoHandler(
+            LOG_BINDING.to_owned(),
+            LOG_NAMESPACE.to_owned(),
+            operation.to_owned(),
+        )
+    })?;
+    log!(target: module, level, "{}", String::from_utf8_lossy(payload));
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatches_to_registered_handler() -> Result<(), Error> {
+        let mut handlers = HostHandlers::new();
+        handlers.register("default", "", "render", |payload| Ok(payload.to_vec()));
+
+        let result = handlers.dispatch("default", "", "render", b"hello")?;
+        assert_eq!(result, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn errors_when_no_handler_matches() {
+        let handlers = HostHandlers::new();
+        let result = handlers.dispatch("default", "", "render", b"hello");
+        assert!(matches!(result, Err(Error::NoHandler(..))));
+    }
+
+    #[test]
+    fn falls_back_when_no_handler_matches() -> Result<(), Error> {
+        let mut handlers = HostHandlers::new();
+        handlers.register("default", "", "render", |_| Ok(b"render".to_vec()));
+        handlers.register_fallback(|_, _, operation, _| Ok(operation.as_bytes().to_vec()));
+
+        assert_eq!(handlers.dispatch("default", "", "render", b"")?, b"render");
+        assert_eq!(handlers.dispatch("default", "", "list", b"")?, b"list");
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn runs_async_handlers_off_the_runtime() -> Result<(), Error> {
+        let mut handlers = HostHandlers::new();
+        handlers.register_async("default", "", "render", |payload| async move {
+            tokio::task::yield_now().await;
+            Ok(payload)
+        });
+
+        let result =
+            std::thread::spawn(move || handlers.dispatch("default", "", "render", b"hello"))
+                .join()
+                .unwrap()?;
+        assert_eq!(result, b"hello");
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn fails_async_handlers_called_on_the_runtime() {
+        let mut handlers = HostHandlers::new();
+        handlers.register_async(
+            "default",
+            "",
+            "render",
+            |payload| async move { Ok(payload) },
+        );
+
+        let result = handlers.dispatch("default", "", "render", b"hello");
+        assert!(
+            matches!(&result, Err(Error::HandlerFailed(_, message)) if message.contains("async tasks")),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn logs_at_guest_levels() {
+        assert!(log("test.wasm", "info", b"hello").is_ok());
+        assert!(log("test.wasm", "WARN", b"hello").is_ok());
+        assert!(matches!(
+            log("test.wasm", "loud", b"hello"),
+            Err(Error::NoHandler(..))
+        ));
+    }
+}
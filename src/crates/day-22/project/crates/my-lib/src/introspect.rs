@@ -0,0 +1,172 @@
+use wasmparser::{ExternalKind, ImportSectionEntryType, Parser, Payload};
+
+use crate::{aot, error::Error, signing, widl::Interface};
+
+/// The custom section guests embed their WIDL interface in.
+pub const INTERFACE_SECTION: &str = "wapc-interface";
+
+/// What a module exports, imports, and declares about its operations.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleInfo {
+    pub exports: Vec<Export>,
+    pub imports: Vec<Import>,
+    /// The interface embedded in the module, if it was built with one.
+    pub interface: Option<Interface>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    pub name: String,
+    pub kind: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub kind: &'static str,
+}
+
+impl ModuleInfo {
+    /// The names of the operations the module's interface declares.
+    ///
+    /// waPC guests register their operations at runtime, so these can only be
+    /// known ahead of a call when the module embeds its interface.
+    pub fn operations(&self) -> Vec<&str> {
+        match &self.interface {
+            Some(interface) => interface
+                .operations
+                .iter()
+                .map(|op| op.name.as_str())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Reads a module's exports, imports and interface. Precompiled modules are
+/// read through the wasm they were compiled from.
+pub fn inspect(bytes: &[u8]) -> Result<ModuleInfo, Error> {
+    let bytes = aot::wasm(bytes)?;
+    let mut info = ModuleInfo::default();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload.map_err(|e| Error::invalid_module(e.to_string()))? {
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.map_err(|e| Error::invalid_module(e.to_string()))?;
+                    info.exports.push(Export {
+                        name: export.field.to_owned(),
+                        kind: export_kind(export.kind),
+                    });
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import.map_err(|e| Error::invalid_module(e.to_string()))?;
+                    info.imports.push(Import {
+                        module: import.module.to_owned(),
+                        name: import.field.unwrap_or_default().to_owned(),
+                        kind: import_kind(&import.ty),
+                    });
+                }
+            }
+            Payload::CustomSection { name, data, .. } if name == INTERFACE_SECTION => {
+                let source = std::str::from_utf8(data)
+                    .map_err(|e| Error::InvalidInterface(e.to_string()))?;
+                info.interface = Some(Interface::parse(source)?);
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+/// Appends `widl` to the module as its embedded interface, for modules that
+/// weren't built with one.
+pub fn embed_interface(bytes: &[u8], widl: &str) -> Result<Vec<u8>, Error> {
+    Interface::parse(widl)?;
+    if aot::is_precompiled(bytes) {
+        return Err(Error::invalid_module(
+            "embed the interface before precompiling the module".to_owned(),
+        ));
+    }
+    if signing::is_signed(bytes) {
+        return Err(Error::invalid_module(
+            "embed the interface before signing the module".to_owned(),
+        ));
+    }
+    if inspect(bytes)?.interface.is_some() {
+        return Err(Error::invalid_module(
+            "module already embeds an interface".to_owned(),
+        ));
+    }
+
+    let mut contents = Vec::new();
+    write_leb128(&mut contents, INTERFACE_SECTION.len());
+    contents.extend(INTERFACE_SECTION.as_bytes());
+    contents.extend(widl.as_bytes());
+
+    let mut embedded = bytes.to_vec();
+    // Custom sections have the id 0.
+    embedded.push(0);
+    write_leb128(&mut embedded, contents.len());
+    embedded.extend(contents);
+    Ok(embedded)
+}
+
+fn write_leb128(buf: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn export_kind(kind: ExternalKind) -> &'static str {
+    match kind {
+        ExternalKind::Function => "function",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+        _ => "other",
+    }
+}
+
+fn import_kind(ty: &ImportSectionEntryType) -> &'static str {
+    match ty {
+        ImportSectionEntryType::Function(_) => "function",
+        ImportSectionEntryType::Table(_) => "table",
+        ImportSectionEntryType::Memory(_) => "memory",
+        ImportSectionEntryType::Global(_) => "global",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_guest_exports() -> Result<(), Error> {
+        let bytes = std::fs::read("./tests/test.wasm").unwrap();
+        let info = inspect(&bytes)?;
+
+        assert!(info.exports.iter().any(|e| e.name == "__guest_call"));
+        assert!(info.imports.iter().any(|i| i.module == "wapc"));
+        Ok(())
+    }
+
+    #[test]
+    fn embeds_interface() -> Result<(), Error> {
+        let bytes = std::fs::read("./tests/test.wasm").unwrap();
+        let embedded = embed_interface(&bytes, "interface { hello(name: string): string }")?;
+
+        let info = inspect(&embedded)?;
+        assert_eq!(info.operations(), ["hello"]);
+        Ok(())
+    }
+}
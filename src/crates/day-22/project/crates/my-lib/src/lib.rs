@@ -1,22 +1,409 @@
+pub mod aot;
+#[cfg(feature = "async")]
+pub mod async_module;
+pub mod codec;
+pub mod config;
+pub mod engine;
 pub mod error;
+#[cfg(test)]
+mod fixtures;
+pub mod golden;
+pub mod host;
+pub mod introspect;
+pub mod limits;
+pub mod metrics;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod permissions;
+pub mod pool;
+pub mod recording;
+pub mod signing;
+pub mod watch;
+pub mod widl;
+mod worker;
 
-use std::{fs, path::Path};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tracing::field;
 use wapc::WapcHost;
 
-use error::Error;
+#[cfg(feature = "wasmtime")]
+use aot::CompileCache;
+use codec::{Codec, CodecKind};
+use config::ModuleConfig;
+use engine::{EngineKind, Exceeded, Failures, Tracked};
+use error::{Error, HostCallFailure};
+use host::{HostHandlers, HostResult};
+use introspect::ModuleInfo;
+use limits::Limits;
+use metrics::{Metrics, Outcome};
+use permissions::Permissions;
+use recording::{Recorder, Recording, Replayer};
 
 #[macro_use]
 extern crate log;
 
 pub struct Module {
     host: WapcHost,
+    codec: CodecKind,
+    info: ModuleInfo,
+    /// Names the module in errors.
+    name: String,
+    failures: Arc<Failures>,
+    memory: Arc<AtomicUsize>,
+    metrics: Option<Arc<dyn Metrics>>,
 }
 
 impl Module {
     pub fn new(bytes: &[u8]) -> Result<Self, Error> {
-        let engine = wasmtime_provider::WasmtimeEngineProvider::new(bytes, None);
+        Self::builder().build(bytes)
+    }
+
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        Self::builder().from_file(path)
+    }
+
+    /// Instantiates a module on a specific engine rather than the default one.
+    pub fn with_engine(engine: EngineKind, bytes: &[u8]) -> Result<Self, Error> {
+        Self::builder().engine(engine).build(bytes)
+    }
+
+    /// Loads a module compiled ahead of time by [aot::compile].
+    pub fn from_precompiled<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        Self::builder().from_precompiled(path)
+    }
+
+    pub fn builder() -> ModuleBuilder {
+        ModuleBuilder::default()
+    }
+
+    /// The exports, imports, and embedded interface of the loaded module.
+    pub fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    /// The size of the guest's linear memory in bytes, as of the last call or
+    /// since it was instantiated. 0 if it doesn't export one.
+    pub fn memory_size(&self) -> usize {
+        self.memory.load(Ordering::Relaxed)
+    }
+
+    /// The operations declared by the module's embedded interface, if it has one.
+    pub fn operations(&self) -> Vec<&str> {
+        self.info.operations()
+    }
+
+    pub fn run(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        debug!("Invoking {}", operation);
+        let span = tracing::info_span!(
+            "run",
+            operation,
+            payload_size = payload.len(),
+            duration_ms = field::Empty,
+            outcome = field::Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.call(operation, payload);
+        let duration = start.elapsed();
+        let outcome = Outcome::of(&result);
+        span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+        span.record("outcome", outcome.as_str());
+        if let Some(metrics) = &self.metrics {
+            metrics.guest_call(operation, payload.len(), duration, outcome);
+        }
+        result
+    }
+
+    fn call(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        self.failures.clear();
+        self.host
+            .call(operation, payload)
+            .map_err(|e| self.call_failed(operation, e))
+    }
+
+    /// Works out why a call failed from what the engine and host calls saw,
+    /// since waPC reports every failure as a `GuestCallFailure` message.
+    fn call_failed(&self, operation: &str, error: wapc::errors::Error) -> Error {
+        let module = self.name.clone();
+        let operation = operation.to_owned();
+        match self.failures.take_exceeded() {
+            Some(Exceeded::Fuel(fuel)) => return Error::OutOfFuel(operation, fuel),
+            Some(Exceeded::Timeout(timeout)) => return Error::Timeout(operation, timeout),
+            None => {}
+        }
+        if let Some(backtrace) = self.failures.take_trap() {
+            return Error::Trap {
+                module,
+                operation,
+                backtrace,
+            };
+        }
+        let message = match error.kind() {
+            wapc::errors::ErrorKind::GuestCallFailure(message) => message.clone(),
+            _ => return Error::WapcError(error),
+        };
+        if let Some(host_call) = self.failures.take_host_call(&message) {
+            return Error::HostCallFailed {
+                module,
+                operation,
+                host_call: Box::new(host_call),
+            };
+        }
+        if message.starts_with(UNKNOWN_OPERATION) {
+            return Error::UnknownOperation { module, operation };
+        }
+        Error::GuestError {
+            module,
+            operation,
+            message,
+        }
+    }
+
+    /// Runs `operation` with `input` encoded by the module's codec and decodes
+    /// the guest's response with it. Inputs are validated first when the
+    /// module embeds its interface.
+    pub fn invoke<I, O>(&self, operation: &str, input: &I) -> Result<O, Error>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        self.validate(operation, input)?;
+        let payload = self.codec.encode(input)?;
+        let result = self.run(operation, &payload)?;
+        self.codec.decode(&result)
+    }
+
+    /// Checks `input` against the types the module's embedded interface
+    /// declares for `operation`. Modules without an interface accept anything.
+    pub fn validate<I: Serialize>(&self, operation: &str, input: &I) -> Result<(), Error> {
+        validate_input(&self.info, self.codec, operation, input)
+    }
+}
+
+pub(crate) fn validate_input<I: Serialize>(
+    info: &ModuleInfo,
+    codec: CodecKind,
+    operation: &str,
+    input: &I,
+) -> Result<(), Error> {
+    // Raw payloads are opaque bytes, so there's nothing to check them against.
+    if codec == CodecKind::Raw {
+        return Ok(());
+    }
+    match &info.interface {
+        Some(interface) => {
+            let value =
+                serde_json::to_value(input).map_err(|e| Error::EncodeFailed(e.to_string()))?;
+            interface.validate(operation, &value)
+        }
+        None => Ok(()),
+    }
+}
+
+/// How waPC guests report a call to an operation they don't have.
+const UNKNOWN_OPERATION: &str = "No handler registered for function";
+
+/// The name of modules that weren't loaded from a file or given a name.
+const UNNAMED: &str = "<unnamed module>";
+
+/// Configures the host side of a [Module] before it is instantiated.
+#[derive(Default, Clone)]
+pub struct ModuleBuilder {
+    handlers: HostHandlers,
+    codec: CodecKind,
+    config: ModuleConfig,
+    engine: EngineKind,
+    #[cfg(feature = "wasmtime")]
+    cache: Option<CompileCache>,
+    name: Option<String>,
+    recording: Option<Recording>,
+    metrics: Option<Arc<dyn Metrics>>,
+    /// Compiled code to share with the other modules built from this builder.
+    shared: Option<engine::Shared>,
+    /// Whether the module may be native code from [aot::compile], which is
+    /// loaded without being validated. Only set by [ModuleBuilder::from_precompiled]
+    /// and the compile cache.
+    precompiled: bool,
+}
+
+impl ModuleBuilder {
+    /// Serves the guest's `host_call(binding, namespace, operation, ..)` with `handler`.
+    pub fn handler<F>(mut self, binding: &str, namespace: &str, operation: &str, handler: F) -> Self
+    where
+        F: Fn(&[u8]) -> HostResult + Send + Sync + 'static,
+    {
+        self.handlers
+            .register(binding, namespace, operation, handler);
+        self
+    }
+
+    /// Serves the guest's `host_call(binding, namespace, operation, ..)` with an async `handler`.
+    #[cfg(feature = "async")]
+    pub fn async_handler<F, Fut>(
+        mut self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = HostResult> + Send + 'static,
+    {
+        self.handlers
+            .register_async(binding, namespace, operation, handler);
+        self
+    }
+
+    pub fn handlers(mut self, handlers: HostHandlers) -> Self {
+        self.handlers = handlers;
+        self
+    }
+
+    /// Selects the codec [Module::invoke] uses, MessagePack by default.
+    pub fn codec(mut self, codec: CodecKind) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn config(mut self, config: ModuleConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Restricts the host calls the module may make.
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.config.permissions = Some(permissions);
+        self
+    }
+
+    /// Records every host call the module makes, and the handler's response,
+    /// with `recorder`.
+    pub fn record(mut self, recorder: Recorder) -> Self {
+        self.recording = Some(Recording::Record(recorder));
+        self
+    }
+
+    /// Answers the module's host calls from a recording instead of the
+    /// handlers, failing any call that wasn't the next one recorded. Keep a
+    /// clone of `replayer` to [Replayer::finish] it once the guest is done.
+    pub fn replay(mut self, replayer: Replayer) -> Self {
+        self.recording = Some(Recording::Replay(replayer));
+        self
+    }
+
+    /// Reports every guest call and host call the module makes to `metrics`.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Names the module in logs, its file path when loaded from a file.
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Selects the engine the module runs on, wasmtime if it's enabled.
+    pub fn engine(mut self, engine: EngineKind) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Compiles wasm through `cache`, so later builds of the same module skip
+    /// compilation. Only used on wasmtime, and never for WASI guests.
+    #[cfg(feature = "wasmtime")]
+    pub fn cache(mut self, cache: CompileCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Instantiates a module from wasm or from a precompiled artifact.
+    pub fn build(self, bytes: &[u8]) -> Result<Module, Error> {
+        let name = self.name.clone();
+        self.instantiate(bytes)
+            .map_err(|e| e.in_module(name.as_deref().unwrap_or(UNNAMED)))
+    }
+
+    fn instantiate(self, bytes: &[u8]) -> Result<Module, Error> {
+        if aot::is_precompiled(bytes) && !self.precompiled {
+            return Err(Error::InvalidArtifact(
+                "precompiled modules run unchecked native code, load them with from_precompiled"
+                    .to_owned(),
+            ));
+        }
+        if let Some(trust) = &self.config.trust {
+            // A signature covers the wasm, not code compiled from it.
+            if aot::is_precompiled(bytes) {
+                return Err(Error::UntrustedModule(
+                    "precompiled modules can't be verified, load the signed wasm".to_owned(),
+                ));
+            }
+            let signer = signing::verify(bytes, trust)?;
+            debug!("Module signed by {}", signing::encode_public_key(&signer));
+        }
 
-        let host = WapcHost::new(Box::new(engine), |_id, binding, ns, operation, payload| {
+        #[cfg(feature = "wasmtime")]
+        let compiled;
+        #[cfg(feature = "wasmtime")]
+        let (bytes, precompiled) = match &self.cache {
+            Some(cache)
+                if self.engine == EngineKind::Wasmtime
+                    && self.config.wasi.is_none()
+                    && !aot::is_precompiled(bytes) =>
+            {
+                compiled = cache.get_or_compile(bytes)?;
+                (&compiled[..], true)
+            }
+            _ => (bytes, self.precompiled),
+        };
+        #[cfg(not(feature = "wasmtime"))]
+        let precompiled = self.precompiled;
+        let wasm = aot::wasm(bytes)?;
+
+        let info = introspect::inspect(wasm)?;
+        if let Some(max_pages) = self.config.limits.max_memory_pages {
+            limits::check_memory(wasm, max_pages, self.engine.enforces_limits())?;
+        }
+
+        let failures = Arc::new(Failures::default());
+        let memory = Arc::new(AtomicUsize::new(0));
+        let engine = Box::new(Tracked::new(
+            engine::provider(
+                self.engine,
+                bytes,
+                &self.config,
+                precompiled,
+                self.shared.as_ref(),
+            )?,
+            failures.clone(),
+            memory.clone(),
+        ));
+
+        let name = self.name.unwrap_or_else(|| UNNAMED.to_owned());
+        let calls = HostCalls {
+            handlers: self.handlers,
+            permissions: self.config.permissions,
+            name: name.clone(),
+            recording: self.recording,
+            failures: failures.clone(),
+        };
+        let metrics = self.metrics.clone();
+        let host = WapcHost::new(engine, move |_id, binding, ns, operation, payload| {
             trace!(
                 "Guest called: binding={}, namespace={}, operation={}, payload={:?}",
                 binding,
@@ -24,25 +411,145 @@ impl Module {
                 operation,
                 payload
             );
-            Err("Not implemented".into())
+            let span = tracing::debug_span!(
+                "host_call",
+                binding,
+                namespace = ns,
+                operation,
+                outcome = field::Empty
+            );
+            let _entered = span.enter();
+            let start = Instant::now();
+            let result = calls.call(binding, ns, operation, payload);
+            let outcome = Outcome::of(&result);
+            span.record("outcome", outcome.as_str());
+            if let Some(metrics) = &metrics {
+                metrics.host_call(binding, ns, operation, start.elapsed(), outcome);
+            }
+            Ok(result?)
         })?;
-        Ok(Module { host })
+        Ok(Module {
+            host,
+            codec: self.codec,
+            info,
+            name,
+            failures,
+            memory,
+            metrics: self.metrics,
+        })
     }
 
-    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+    pub fn from_file<T: AsRef<Path>>(self, path: T) -> Result<Module, Error> {
         debug!("Loading wasm file from {:?}", path.as_ref());
-        let bytes = fs::read(path.as_ref())
-            .map_err(|e| Error::FileNotReadable(path.as_ref().to_path_buf(), e.to_string()))?;
-        Self::new(&bytes)
+        let bytes = read(path.as_ref())?;
+        self.for_file(path.as_ref())?.build(&bytes)
     }
 
-    pub fn run(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
-        debug!("Invoking {}", operation);
-        let result = self.host.call(operation, payload)?;
-        Ok(result)
+    /// Like [ModuleBuilder::from_file], but fails unless the file holds a
+    /// module compiled by [aot::compile]. Its native code is run as is, so only
+    /// load files you compiled yourself.
+    pub fn from_precompiled<T: AsRef<Path>>(mut self, path: T) -> Result<Module, Error> {
+        debug!("Loading precompiled module from {:?}", path.as_ref());
+        let bytes = read(path.as_ref())?;
+        if !aot::is_precompiled(&bytes) {
+            return Err(Error::InvalidArtifact(format!(
+                "{} is not a precompiled module",
+                path.as_ref().display()
+            )));
+        }
+        self.precompiled = true;
+        self.for_file(path.as_ref())?.build(&bytes)
+    }
+
+    /// Names the module after `path` and loads the permissions manifest next
+    /// to it, unless permissions were already configured. Use with [read] to
+    /// build several instances of a file.
+    pub fn for_file(mut self, path: &Path) -> Result<Self, Error> {
+        if self.name.is_none() {
+            self.name = Some(path.display().to_string());
+        }
+        let manifest = permissions::manifest_path(path);
+        if self.config.permissions.is_none() && manifest.exists() {
+            debug!("Loading permissions from {}", manifest.display());
+            self.config.permissions = Some(Permissions::load(&manifest)?);
+        }
+        Ok(self)
     }
 }
 
+/// Serves a module's host calls.
+struct HostCalls {
+    handlers: HostHandlers,
+    permissions: Option<Permissions>,
+    name: String,
+    recording: Option<Recording>,
+    failures: Arc<Failures>,
+}
+
+impl HostCalls {
+    /// Serves a host call, noting it if it fails.
+    fn call(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        self.dispatch(binding, namespace, operation, payload)
+            .inspect_err(|e| {
+                self.failures.host_call_failed(HostCallFailure {
+                    binding: binding.to_owned(),
+                    namespace: namespace.to_owned(),
+                    operation: operation.to_owned(),
+                    message: e.to_string(),
+                })
+            })
+    }
+
+    fn dispatch(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        // Logging is always allowed, and isn't recorded since it doesn't
+        // change what the guest does.
+        if (binding, namespace) == (host::LOG_BINDING, host::LOG_NAMESPACE) {
+            return host::log(&self.name, operation, payload);
+        }
+        if let Some(permissions) = &self.permissions {
+            if !permissions.allows(binding, namespace, operation) {
+                warn!(
+                    "Denied host call from {}: binding={}, namespace={}, operation={}",
+                    self.name, binding, namespace, operation
+                );
+                return Err(Error::PermissionDenied(
+                    self.name.clone(),
+                    binding.to_owned(),
+                    namespace.to_owned(),
+                    operation.to_owned(),
+                ));
+            }
+        }
+        match &self.recording {
+            Some(recording) => {
+                recording.dispatch(&self.handlers, binding, namespace, operation, payload)
+            }
+            None => self
+                .handlers
+                .dispatch(binding, namespace, operation, payload),
+        }
+    }
+}
+
+/// Reads a module, embedding the signature from its `.sig` file if it has one.
+pub fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    let bytes =
+        fs::read(path).map_err(|e| Error::FileNotReadable(path.to_path_buf(), e.to_string()))?;
+    signing::attach_detached(path, bytes)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,4 +569,73 @@ mod tests {
         assert_eq!(unpacked, "Hello, World.");
         Ok(())
     }
+
+    #[test]
+    fn invokes_operation() -> Result<(), Error> {
+        let module = Module::from_file("./tests/test.wasm")?;
+
+        let result: String = module.invoke("hello", &"World")?;
+        assert_eq!(result, "Hello, World.");
+        Ok(())
+    }
+
+    #[test]
+    fn tells_failures_apart() -> Result<(), Error> {
+        let module = Module::from_file("./tests/test.wasm")?;
+        let result = module.run("missing", b"");
+        assert!(
+            matches!(&result, Err(Error::UnknownOperation { module, operation })
+                if module == "./tests/test.wasm" && operation == "missing"),
+            "{:?}",
+            result
+        );
+
+        let trapping = wat::parse_str(
+            r#"(module
+                (func (export "__guest_call") (param i32 i32) (result i32) unreachable))"#,
+        )
+        .unwrap();
+        for engine in EngineKind::available() {
+            let result = Module::with_engine(engine, &trapping)?.run("hello", b"");
+            assert!(matches!(result, Err(Error::Trap { .. })), "on {}", engine);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reports_guest_memory() -> Result<(), Error> {
+        // A guest that grows its one-page memory by a page on every call.
+        let growing = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "__guest_call") (param i32 i32) (result i32)
+                    (drop (memory.grow (i32.const 1)))
+                    (i32.const 0)))"#,
+        )
+        .unwrap();
+        for engine in EngineKind::available() {
+            let module = Module::with_engine(engine, &growing)?;
+            assert_eq!(module.memory_size(), 64 * 1024, "on {}", engine);
+            let _ = module.run("grow", b"");
+            assert_eq!(module.memory_size(), 2 * 64 * 1024, "on {}", engine);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn engines_agree() -> Result<(), Error> {
+        let bytes = fs::read("./tests/test.wasm").unwrap();
+        for engine in EngineKind::available() {
+            let module = Module::with_engine(engine, &bytes)?;
+            let result: String = module.invoke("hello", &"World")?;
+            assert_eq!(result, "Hello, World.", "on {}", engine);
+            let result = module.run("missing", b"");
+            assert!(
+                matches!(result, Err(Error::UnknownOperation { .. })),
+                "on {}",
+                engine
+            );
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use wasmparser::{Parser, Payload};
+
+use crate::error::Error;
+
+/// The size of a page of linear memory.
+#[cfg_attr(not(feature = "wasmtime"), allow(dead_code))]
+pub(crate) const PAGE_SIZE: usize = 64 * 1024;
+
+/// The most pages a 32-bit memory can grow to.
+const MAX_PAGES: u64 = 65536;
+
+/// Execution limits that keep a misbehaving guest from taking down the host.
+/// See [crate::engine::EngineKind::enforces_limits] for which engines apply them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// How long a single guest call may run before it is interrupted.
+    pub timeout: Option<Duration>,
+    /// How much fuel a single guest call may use, about one unit per instruction.
+    pub fuel: Option<u64>,
+    /// The most pages of linear memory the guest may have.
+    pub max_memory_pages: Option<u32>,
+}
+
+/// Rejects modules whose memories start out larger than `max_pages`, or when
+/// the engine can't stop `memory.grow`, that may grow larger than it.
+pub(crate) fn check_memory(bytes: &[u8], max_pages: u32, limits_growth: bool) -> Result<(), Error> {
+    for payload in Parser::new(0).parse_all(bytes) {
+        let payload = payload.map_err(|e| Error::invalid_module(e.to_string()))?;
+        if let Payload::MemorySection(reader) = payload {
+            for memory in reader {
+                let memory = memory.map_err(|e| Error::invalid_module(e.to_string()))?;
+                let requested = match limits_growth {
+                    true => memory.initial,
+                    false => memory.maximum.unwrap_or(MAX_PAGES),
+                };
+                if requested > max_pages as u64 {
+                    return Err(Error::MemoryLimit(requested, max_pages));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "wasmtime")]
+    use crate::Module;
+
+    /// A module with a single memory of `initial` pages and no maximum.
+    fn module_with_memory(initial: u8) -> Vec<u8> {
+        wat::parse_str(format!("(module (memory {}))", initial)).unwrap()
+    }
+
+    #[test]
+    fn rejects_memory_over_the_limit() {
+        let result = check_memory(&module_with_memory(2), 1, true);
+        assert!(matches!(result, Err(Error::MemoryLimit(2, 1))));
+        assert!(check_memory(&module_with_memory(2), 4, true).is_ok());
+        // Without a maximum, a module may grow as far as wasm allows.
+        let result = check_memory(&module_with_memory(2), 4, false);
+        assert!(matches!(result, Err(Error::MemoryLimit(MAX_PAGES, 4))));
+    }
+
+    /// A guest whose __guest_call never returns.
+    #[cfg(feature = "wasmtime")]
+    fn spinning_module() -> Vec<u8> {
+        wat::parse_str(
+            r#"(module
+                (func (export "__guest_call") (param i32 i32) (result i32)
+                    (loop (br 0))
+                    unreachable))"#,
+        )
+        .unwrap()
+    }
+
+    #[cfg(feature = "wasmtime")]
+    #[test]
+    fn stops_guests_that_run_too_long() -> Result<(), Error> {
+        let timeout = Duration::from_millis(50);
+        let module = Module::builder()
+            .limits(Limits {
+                timeout: Some(timeout),
+                ..Default::default()
+            })
+            .build(&spinning_module())?;
+        for _ in 0..2 {
+            let result = module.run("spin", b"");
+            assert!(
+                matches!(&result, Err(Error::Timeout(operation, t)) if operation == "spin" && *t == timeout),
+                "{:?}",
+                result
+            );
+        }
+
+        let module = Module::builder()
+            .limits(Limits {
+                fuel: Some(10_000),
+                ..Default::default()
+            })
+            .build(&spinning_module())?;
+        let result = module.run("spin", b"");
+        assert!(
+            matches!(result, Err(Error::OutOfFuel(_, 10_000))),
+            "{:?}",
+            result
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "wasmtime")]
+    #[test]
+    fn initializes_guests_within_the_fuel_limit() -> Result<(), Error> {
+        // A guest whose wapc_init counts to 100 before any call is made.
+        let initializing = wat::parse_str(
+            r#"(module
+                (import "wapc" "__guest_response" (func $respond (param i32 i32)))
+                (memory (export "memory") 1)
+                (global $count (mut i32) (i32.const 0))
+                (func (export "wapc_init")
+                    (loop
+                        (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                        (br_if 0 (i32.lt_u (global.get $count) (i32.const 100)))))
+                (func (export "__guest_call") (param i32 i32) (result i32)
+                    (call $respond (i32.const 0) (i32.const 0))
+                    (i32.const 1)))"#,
+        )
+        .unwrap();
+
+        let module = Module::builder()
+            .limits(Limits {
+                fuel: Some(1_000_000),
+                ..Default::default()
+            })
+            .build(&initializing)?;
+        for _ in 0..2 {
+            module.run("count", b"")?;
+        }
+        let result = Module::builder()
+            .limits(Limits {
+                fuel: Some(10),
+                ..Default::default()
+            })
+            .build(&initializing);
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "wasmtime")]
+    #[test]
+    fn stops_memory_growing_past_the_limit() -> Result<(), Error> {
+        // A guest that grows its one-page memory by a page, trapping if it can't.
+        let growing = wat::parse_str(
+            r#"(module
+                (memory 1)
+                (func (export "__guest_call") (param i32 i32) (result i32)
+                    (if (i32.lt_s (memory.grow (i32.const 1)) (i32.const 0))
+                        (then unreachable))
+                    (i32.const 0)))"#,
+        )
+        .unwrap();
+
+        let limited = |pages| Limits {
+            max_memory_pages: Some(pages),
+            ..Default::default()
+        };
+        let result = Module::builder()
+            .limits(limited(1))
+            .build(&growing)?
+            .run("grow", b"");
+        assert!(matches!(result, Err(Error::Trap { .. })), "{:?}", result);
+        let result = Module::builder()
+            .limits(limited(2))
+            .build(&growing)?
+            .run("grow", b"");
+        assert!(
+            matches!(result, Err(Error::GuestError { .. })),
+            "{:?}",
+            result
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Receives a measurement for every guest call and host call a module makes,
+/// for a service to aggregate and export however it likes.
+///
+/// Every method does nothing by default, so implementations only override
+/// what they're interested in.
+pub trait Metrics: Send + Sync {
+    /// A call to the guest's `operation` with a `payload_size` byte payload finished.
+    fn guest_call(
+        &self,
+        operation: &str,
+        payload_size: usize,
+        duration: Duration,
+        outcome: Outcome,
+    ) {
+        let _ = (operation, payload_size, duration, outcome);
+    }
+
+    /// The guest's call to the host's `binding`/`namespace`/`operation` finished.
+    fn host_call(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        duration: Duration,
+        outcome: Outcome,
+    ) {
+        let _ = (binding, namespace, operation, duration, outcome);
+    }
+}
+
+/// How a call ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error,
+    Timeout,
+    /// The guest has no such operation.
+    UnknownOperation,
+}
+
+impl Outcome {
+    pub fn of<T>(result: &Result<T, Error>) -> Self {
+        match result {
+            Ok(_) => Outcome::Ok,
+            Err(Error::Timeout(..)) => Outcome::Timeout,
+            Err(Error::UnknownOperation { .. }) => Outcome::UnknownOperation,
+            Err(_) => Outcome::Error,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Error => "error",
+            Outcome::Timeout => "timeout",
+            Outcome::UnknownOperation => "unknown_operation",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::Module;
+
+    #[derive(Default)]
+    struct Calls(Mutex<Vec<(String, usize, Outcome)>>);
+
+    impl Metrics for Calls {
+        fn guest_call(&self, operation: &str, payload_size: usize, _: Duration, outcome: Outcome) {
+            let call = (operation.to_owned(), payload_size, outcome);
+            self.0.lock().unwrap().push(call);
+        }
+    }
+
+    #[test]
+    fn reports_each_call_once() -> Result<(), Error> {
+        let calls = Arc::new(Calls::default());
+        let module = Module::builder()
+            .metrics(calls.clone())
+            .from_file("./tests/test.wasm")?;
+        let _: String = module.invoke("hello", &"World")?;
+        assert!(module.run("missing", b"").is_err());
+
+        let calls = calls.0.lock().unwrap();
+        assert_eq!(
+            *calls,
+            [
+                ("hello".to_owned(), 6, Outcome::Ok),
+                ("missing".to_owned(), 0, Outcome::UnknownOperation)
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+};
+
+use crate::host::{HostHandlers, HostResult};
+
+/// Stands in for a real host when unit testing a guest. Each expectation
+/// answers the host calls that match it, and the mock panics when dropped if
+/// an expectation wasn't met or the guest made a call none of them expected.
+/// Available with the `test-utils` feature.
+///
+/// ```no_run
+/// # use my_lib::{mock::MockHost, Module};
+/// let host = MockHost::new()
+///     .expect("default", "", "render")
+///     .with_payload(b"post".to_vec())
+///     .returning(b"<p>post</p>".to_vec())
+///     .times(2);
+/// let module = Module::builder()
+///     .handlers(host.handlers())
+///     .from_file("blog.wasm")
+///     .unwrap();
+/// ```
+pub struct MockHost {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    expectations: Vec<Expectation>,
+    unexpected: Vec<String>,
+}
+
+struct Expectation {
+    binding: String,
+    namespace: String,
+    operation: String,
+    payload: Option<Vec<u8>>,
+    response: Result<Vec<u8>, String>,
+    /// How many calls are expected, at least one when unset.
+    times: Option<usize>,
+    calls: usize,
+}
+
+impl Expectation {
+    fn matches(&self, binding: &str, namespace: &str, operation: &str, payload: &[u8]) -> bool {
+        (
+            self.binding.as_str(),
+            self.namespace.as_str(),
+            self.operation.as_str(),
+        ) == (binding, namespace, operation)
+            && self.payload.as_deref().is_none_or(|p| p == payload)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.times.is_some_and(|times| self.calls >= times)
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "binding={}, namespace={}, operation={}",
+            self.binding, self.namespace, self.operation
+        )
+    }
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        MockHost {
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Expects a call to `binding`/`namespace`/`operation`. The methods that
+    /// follow refine this expectation until the next call to `expect`.
+    pub fn expect(self, binding: &str, namespace: &str, operation: &str) -> Self {
+        self.state().expectations.push(Expectation {
+            binding: binding.to_owned(),
+            namespace: namespace.to_owned(),
+            operation: operation.to_owned(),
+            payload: None,
+            response: Ok(Vec::new()),
+            times: None,
+            calls: 0,
+        });
+        self
+    }
+
+    /// Only matches calls with exactly this payload.
+    pub fn with_payload<T: Into<Vec<u8>>>(self, payload: T) -> Self {
+        self.last(|expectation| expectation.payload = Some(payload.into()))
+    }
+
+    /// Answers matching calls with `response`, an empty payload by default.
+    pub fn returning<T: Into<Vec<u8>>>(self, response: T) -> Self {
+        self.last(|expectation| expectation.response = Ok(response.into()))
+    }
+
+    /// Fails matching calls with `message`.
+    pub fn returning_error<T: Into<String>>(self, message: T) -> Self {
+        self.last(|expectation| expectation.response = Err(message.into()))
+    }
+
+    /// Expects exactly `times` matching calls rather than at least one.
+    pub fn times(self, times: usize) -> Self {
+        self.last(|expectation| expectation.times = Some(times))
+    }
+
+    /// Handlers that route every call the guest makes to this mock, to pass
+    /// to [crate::ModuleBuilder::handlers]. Expectations added afterwards
+    /// still apply.
+    pub fn handlers(&self) -> HostHandlers {
+        let mut handlers = HostHandlers::new();
+        let state = self.state.clone();
+        handlers.register_fallback(move |binding, namespace, operation, payload| {
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.call(binding, namespace, operation, payload)
+        });
+        handlers
+    }
+
+    /// Panics if an expectation wasn't met or the guest made an unexpected call.
+    pub fn verify(&self) {
+        let state = self.state();
+        let mut failures = state.unexpected.clone();
+        for expectation in &state.expectations {
+            match expectation.times {
+                Some(times) if expectation.calls != times => failures.push(format!(
+                    "expected {} {} times, called {} times",
+                    expectation.describe(),
+                    times,
+                    expectation.calls
+                )),
+                None if expectation.calls == 0 => {
+                    failures.push(format!("expected {}, never called", expectation.describe()))
+                }
+                _ => {}
+            }
+        }
+        if !failures.is_empty() {
+            panic!("MockHost expectations failed:\n  {}", failures.join("\n  "));
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn last(self, update: impl FnOnce(&mut Expectation)) -> Self {
+        let mut state = self.state();
+        let expectation = state
+            .expectations
+            .last_mut()
+            .expect("call MockHost::expect first");
+        update(expectation);
+        drop(state);
+        self
+    }
+}
+
+impl Default for MockHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MockHost {
+    fn drop(&mut self) {
+        // Don't turn a failing test's panic into an abort.
+        if !thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+impl State {
+    fn call(
+        &mut self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        payload: &[u8],
+    ) -> HostResult {
+        let expectation = self
+            .expectations
+            .iter_mut()
+            .find(|e| e.matches(binding, namespace, operation, payload) && !e.exhausted());
+        match expectation {
+            Some(expectation) => {
+                expectation.calls += 1;
+                expectation.response.clone().map_err(|e| e.into())
+            }
+            None => {
+                let call = format!(
+                    "unexpected call binding={}, namespace={}, operation={}, payload={:?}",
+                    binding, namespace, operation, payload
+                );
+                self.unexpected.push(call.clone());
+                Err(call.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn answers_expected_calls() -> Result<(), Error> {
+        let host = MockHost::new()
+            .expect("default", "", "render")
+            .with_payload(b"post".to_vec())
+            .returning(b"<p>post</p>".to_vec())
+            .times(2)
+            .expect("default", "", "render")
+            .returning_error("not found");
+        let handlers = host.handlers();
+
+        for _ in 0..2 {
+            let result = handlers.dispatch("default", "", "render", b"post")?;
+            assert_eq!(result, b"<p>post</p>");
+        }
+        let result = handlers.dispatch("default", "", "render", b"post");
+        assert!(matches!(result, Err(Error::HandlerFailed(..))));
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected binding=default, namespace=, operation=render 2 times, called 1 times"
+    )]
+    fn panics_when_expectations_are_unmet() {
+        let host = MockHost::new().expect("default", "", "render").times(2);
+        let _ = host.handlers().dispatch("default", "", "render", b"post");
+    }
+
+    #[test]
+    fn applies_expectations_added_later() -> Result<(), Error> {
+        let host = MockHost::new();
+        let handlers = host.handlers();
+        let host = host
+            .expect("default", "", "render")
+            .returning(b"page".to_vec());
+
+        assert_eq!(handlers.dispatch("default", "", "render", b"")?, b"page");
+        host.verify();
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected call binding=default, namespace=, operation=list")]
+    fn panics_on_calls_to_unexpected_operations() {
+        let host = MockHost::new().expect("default", "", "render");
+        let handlers = host.handlers();
+        let _ = handlers.dispatch("default", "", "render", b"post");
+        let _ = handlers.dispatch("default", "", "list", b"");
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected call")]
+    fn panics_on_unexpected_calls() {
+        let host = MockHost::new()
+            .expect("default", "", "render")
+            .with_payload(b"post".to_vec());
+        let handlers = host.handlers();
+        let _ = handlers.dispatch("default", "", "render", b"post");
+        let _ = handlers.dispatch("default", "", "render", b"page");
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// The host calls a module may make. Calls that no rule allows are denied
+/// before they reach a handler.
+///
+/// Permissions are usually loaded from a manifest next to the module, so
+/// `echo.wasm` is governed by `echo.permissions.toml`:
+///
+/// ```toml
+/// [[allow]]
+/// binding = "default"
+/// namespace = "http"    # any namespace when left out
+/// operations = ["get"]  # any operation when left out
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Permissions {
+    #[serde(default)]
+    pub allow: Vec<Rule>,
+}
+
+/// Allows calls to a binding, optionally narrowed to a namespace and operations.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub binding: String,
+    pub namespace: Option<String>,
+    pub operations: Option<Vec<String>>,
+}
+
+impl Permissions {
+    /// Denies every host call.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Allows calls matching `rule`.
+    pub fn allow(mut self, rule: Rule) -> Self {
+        self.allow.push(rule);
+        self
+    }
+
+    pub fn parse(manifest: &str) -> Result<Self, Error> {
+        toml::from_str(manifest).map_err(|e| Error::InvalidPermissions(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let manifest = fs::read_to_string(path)
+            .map_err(|e| Error::FileNotReadable(path.to_path_buf(), e.to_string()))?;
+        Self::parse(&manifest)
+            .map_err(|e| Error::InvalidPermissions(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn allows(&self, binding: &str, namespace: &str, operation: &str) -> bool {
+        self.allow
+            .iter()
+            .any(|rule| rule.matches(binding, namespace, operation))
+    }
+}
+
+impl Rule {
+    pub fn binding<T: Into<String>>(binding: T) -> Self {
+        Rule {
+            binding: binding.into(),
+            namespace: None,
+            operations: None,
+        }
+    }
+
+    pub fn namespace<T: Into<String>>(mut self, namespace: T) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn operation<T: Into<String>>(mut self, operation: T) -> Self {
+        self.operations
+            .get_or_insert_with(Vec::new)
+            .push(operation.into());
+        self
+    }
+
+    fn matches(&self, binding: &str, namespace: &str, operation: &str) -> bool {
+        self.binding == binding
+            && self.namespace.as_deref().is_none_or(|ns| ns == namespace)
+            && self
+                .operations
+                .as_ref()
+                .is_none_or(|ops| ops.iter().any(|op| op == operation))
+    }
+}
+
+/// Where the permissions manifest for the module at `path` is kept.
+pub fn manifest_path(path: &Path) -> PathBuf {
+    path.with_extension("permissions.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_only_matching_calls() -> Result<(), Error> {
+        let permissions = Permissions::parse(
+            r#"
+            [[allow]]
+            binding = "default"
+            namespace = "http"
+            operations = ["get"]
+
+            [[allow]]
+            binding = "logging"
+            "#,
+        )?;
+        assert!(permissions.allows("default", "http", "get"));
+        assert!(permissions.allows("logging", "anything", "info"));
+        assert!(!permissions.allows("default", "http", "post"));
+        assert!(!permissions.allows("default", "fs", "get"));
+        assert!(!Permissions::none().allows("default", "http", "get"));
+        Ok(())
+    }
+
+    #[test]
+    fn loads_manifest_next_to_module() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("my-lib-permissions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.wasm");
+        fs::copy("./tests/test.wasm", &path).unwrap();
+        fs::write(manifest_path(&path), "[[allow]]\nbinding = \"default\"\n").unwrap();
+
+        let builder = crate::Module::builder().for_file(&path)?;
+        let permissions = builder.config.permissions.as_ref().unwrap();
+        assert!(permissions.allows("default", "http", "get"));
+        assert!(!permissions.allows("other", "http", "get"));
+        builder.build(&fs::read(&path).unwrap())?;
+
+        fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let result = Permissions::parse("[[allow]]\nbinding = \"default\"\nnamespaces = []\n");
+        assert!(matches!(result, Err(Error::InvalidPermissions(_))));
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    codec::{Codec, CodecKind},
+    error::Error,
+    introspect::{self, ModuleInfo},
+    validate_input,
+    worker::{self, Worker},
+    ModuleBuilder,
+};
+
+/// The id of the instance that ran a job, along with its result.
+type Reply = (usize, Result<Vec<u8>, Error>);
+
+type Job = worker::Job<mpsc::Sender<Reply>>;
+
+/// A fixed set of [crate::Module] instances that serve calls from any thread.
+///
+/// A `WapcHost` can't move between threads, so each instance lives on its own
+/// worker thread and calls are handed to whichever worker is idle.
+pub struct ModulePool {
+    sender: Option<Sender<Job>>,
+    idle: Idle,
+    workers: Vec<JoinHandle<()>>,
+    codec: CodecKind,
+    info: ModuleInfo,
+}
+
+impl ModulePool {
+    /// Instantiates `size` modules from `bytes` with the configuration in
+    /// `builder`. The module is compiled once and shared by every instance.
+    pub fn new(mut builder: ModuleBuilder, bytes: &[u8], size: usize) -> Result<Self, Error> {
+        debug!("Starting module pool with {} instances", size);
+        builder.shared = Some(Default::default());
+        let info = introspect::inspect(bytes)?;
+        let bytes: Arc<[u8]> = bytes.into();
+        let codec = builder.codec;
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let mut workers = Vec::with_capacity(size);
+        for id in 0..size {
+            let builder = builder.clone();
+            let bytes = bytes.clone();
+            let receiver = receiver.clone();
+            let ready = ready_tx.clone();
+            workers.push(thread::spawn(move || {
+                let ready = |result: Result<(), Error>| {
+                    let _ = ready.send(result.map_err(|e| Error::InstanceFailed(id, Box::new(e))));
+                };
+                if let Some(worker) = Worker::start(id, builder, &bytes, ready) {
+                    worker.serve(|| receiver.lock().ok()?.recv().ok());
+                }
+            }));
+        }
+        drop(ready_tx);
+
+        let pool = ModulePool {
+            sender: Some(sender),
+            idle: Idle::new(size),
+            workers,
+            codec,
+            info,
+        };
+        for result in ready_rx.iter().take(size) {
+            result?;
+        }
+        Ok(pool)
+    }
+
+    /// Runs `operation` on the next idle instance, waiting for one if they are all busy.
+    pub fn run(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        self.idle.take();
+        let result = self.call(operation, payload);
+        self.idle.release();
+        result
+    }
+
+    /// Runs `operation` on an idle instance, failing with [Error::PoolExhausted]
+    /// rather than waiting if they are all busy.
+    pub fn try_run(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        if !self.idle.try_take() {
+            return Err(Error::PoolExhausted(self.size()));
+        }
+        let result = self.call(operation, payload);
+        self.idle.release();
+        result
+    }
+
+    /// The pooled equivalent of [crate::Module::invoke].
+    pub fn invoke<I, O>(&self, operation: &str, input: &I) -> Result<O, Error>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        validate_input(&self.info, self.codec, operation, input)?;
+        let payload = self.codec.encode(input)?;
+        let result = self.run(operation, &payload)?;
+        self.codec.decode(&result)
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Hands a call to the workers, once the caller has claimed an idle one.
+    fn call(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let sender = self.sender.as_ref().ok_or(Error::ModuleClosed)?;
+        let (reply, receiver) = mpsc::channel();
+        sender
+            .send(Job::new(operation, payload, reply))
+            .map_err(|_| Error::ModuleClosed)?;
+        Self::wait(receiver)
+    }
+
+    fn wait(reply: Receiver<Reply>) -> Result<Vec<u8>, Error> {
+        let (id, result) = reply.recv().map_err(|_| Error::ModuleClosed)?;
+        result.map_err(|e| Error::InstanceFailed(id, Box::new(e)))
+    }
+}
+
+impl Drop for ModulePool {
+    fn drop(&mut self) {
+        // Closing the channel tells each worker to exit once its current call finishes.
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// How many instances have no call claimed for them. Callers claim one before
+/// handing over a call and release it once they have the result, so a worker
+/// that is between calls still counts as idle.
+struct Idle {
+    count: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Idle {
+    fn new(count: usize) -> Self {
+        Idle {
+            count: Mutex::new(count),
+            released: Condvar::new(),
+        }
+    }
+
+    fn take(&self) {
+        let mut count = self.count.lock().unwrap_or_else(PoisonError::into_inner);
+        while *count == 0 {
+            count = self
+                .released
+                .wait(count)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *count -= 1;
+    }
+
+    fn try_take(&self) -> bool {
+        let mut count = self.count.lock().unwrap_or_else(PoisonError::into_inner);
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    fn release(&self) {
+        *self.count.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        self.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use super::*;
+    use crate::{fixtures, Module};
+
+    #[test]
+    fn runs_operations_across_threads() -> Result<(), Error> {
+        let bytes = std::fs::read("./tests/test.wasm").unwrap();
+        let pool = Arc::new(ModulePool::new(Module::builder(), &bytes, 2)?);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || pool.invoke::<_, String>("hello", &"World"))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap()?, "Hello, World.");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn runs_right_away_on_an_idle_pool() -> Result<(), Error> {
+        let bytes = std::fs::read("./tests/test.wasm").unwrap();
+        let pool = ModulePool::new(Module::builder(), &bytes, 1)?;
+        for _ in 0..100 {
+            let payload = pool.codec.encode(&"World")?;
+            pool.try_run("hello", &payload)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fails_fast_when_every_instance_is_busy() -> Result<(), Error> {
+        // Each call blocks in the host until the test lets it go.
+        let (entered, release) = (Arc::new(Barrier::new(3)), Arc::new(Barrier::new(3)));
+        let builder = {
+            let (entered, release) = (entered.clone(), release.clone());
+            Module::builder().handler("db", "", "get", move |key| {
+                entered.wait();
+                release.wait();
+                Ok(key.to_vec())
+            })
+        };
+        let pool = Arc::new(ModulePool::new(builder, &fixtures::forwarding(), 2)?);
+
+        let calls: Vec<_> = (0..2)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || pool.run("get", b"key"))
+            })
+            .collect();
+        entered.wait();
+        assert!(matches!(
+            pool.try_run("get", b"key"),
+            Err(Error::PoolExhausted(2))
+        ));
+        release.wait();
+        for call in calls {
+            assert_eq!(call.join().unwrap()?, b"key");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, host::HostHandlers};
+
+/// A host call made by a guest and what the host answered, one JSON object
+/// per line of a recording. Payloads are hex-encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostCall {
+    pub binding: String,
+    pub namespace: String,
+    pub operation: String,
+    #[serde(with = "hex_bytes")]
+    pub payload: Vec<u8>,
+    #[serde(flatten)]
+    pub response: Response,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Ok(#[serde(with = "hex_bytes")] Vec<u8>),
+    /// The handler failed with this message.
+    Err(String),
+}
+
+/// Serves host calls from the handlers while recording them, or from a
+/// previous recording instead of the handlers.
+#[derive(Clone)]
+pub(crate) enum Recording {
+    Record(Recorder),
+    Replay(Replayer),
+}
+
+impl Recording {
+    pub(crate) fn dispatch(
+        &self,
+        handlers: &HostHandlers,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        match self {
+            Recording::Record(recorder) => {
+                let result = handlers.dispatch(binding, namespace, operation, payload);
+                let response = match &result {
+                    Ok(response) => Response::Ok(response.clone()),
+                    Err(e) => Response::Err(e.to_string()),
+                };
+                recorder.record(HostCall {
+                    binding: binding.to_owned(),
+                    namespace: namespace.to_owned(),
+                    operation: operation.to_owned(),
+                    payload: payload.to_vec(),
+                    response,
+                })?;
+                result
+            }
+            Recording::Replay(replayer) => replayer.replay(binding, namespace, operation, payload),
+        }
+    }
+}
+
+/// Appends every host call to a file as it happens.
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Recorder {
+    /// Starts a new recording at `path`, replacing any there already.
+    pub fn create<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|e| Error::FileNotWritable(path.to_path_buf(), e.to_string()))?;
+        Ok(Recorder {
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    fn record(&self, call: HostCall) -> Result<(), Error> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let line = serde_json::to_string(&call).map_err(|e| Error::EncodeFailed(e.to_string()))?;
+        writeln!(file, "{}", line)
+            .and_then(|_| file.flush())
+            .map_err(|e| Error::InvalidRecording(e.to_string()))
+    }
+}
+
+/// Answers host calls with the responses from a recording, in the order they
+/// were recorded. Any call other than the next one recorded fails, and so
+/// does [Replayer::finish] if the guest didn't make every recorded call.
+#[derive(Clone)]
+pub struct Replayer {
+    calls: Arc<Vec<HostCall>>,
+    next: Arc<Mutex<usize>>,
+}
+
+impl Replayer {
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::FileNotReadable(path.to_path_buf(), e.to_string()))?;
+        let calls = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    Error::InvalidRecording(format!("{} line {}: {}", path.display(), i + 1, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(calls))
+    }
+
+    pub fn new(calls: Vec<HostCall>) -> Self {
+        Replayer {
+            calls: Arc::new(calls),
+            next: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// The recorded calls the guest hasn't made yet.
+    pub fn remaining(&self) -> &[HostCall] {
+        let next = *self.next.lock().unwrap_or_else(PoisonError::into_inner);
+        &self.calls[next..]
+    }
+
+    /// Checks that the guest made every recorded call, once it's done.
+    pub fn finish(&self) -> Result<(), Error> {
+        match self.remaining() {
+            [] => Ok(()),
+            remaining @ [call, ..] => Err(Error::UnusedRecording(
+                remaining.len(),
+                format!(
+                    "binding={}, namespace={}, operation={}",
+                    call.binding, call.namespace, call.operation
+                ),
+            )),
+        }
+    }
+
+    fn replay(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+        let call = self.calls.get(*next).ok_or_else(|| {
+            Error::UnexpectedHostCall(format!(
+                "binding={}, namespace={}, operation={} after the last recorded call",
+                binding, namespace, operation
+            ))
+        })?;
+        if (
+            call.binding.as_str(),
+            call.namespace.as_str(),
+            call.operation.as_str(),
+        ) != (binding, namespace, operation)
+            || call.payload != payload
+        {
+            return Err(Error::UnexpectedHostCall(format!(
+                "binding={}, namespace={}, operation={}, expected binding={}, namespace={}, operation={} with the recorded payload",
+                binding, namespace, operation, call.binding, call.namespace, call.operation
+            )));
+        }
+        *next += 1;
+        match &call.response {
+            Response::Ok(response) => Ok(response.clone()),
+            // The guest sees the same message it did when the call was recorded.
+            Response::Err(e) => Err(Error::RecordedError(e.clone())),
+        }
+    }
+}
+
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_recorded_calls() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("my-lib-recording-{}", std::process::id()));
+        let mut handlers = HostHandlers::new();
+        handlers.register("default", "kv", "get", |key| Ok([key, b"=1"].concat()));
+
+        let recording = Recording::Record(Recorder::create(&path)?);
+        recording.dispatch(&handlers, "default", "kv", "get", b"a")?;
+        assert!(recording
+            .dispatch(&handlers, "default", "kv", "set", b"a")
+            .is_err());
+
+        let replayer = Replayer::load(&path)?;
+        let replay = Recording::Replay(replayer.clone());
+        let none = HostHandlers::new();
+        assert_eq!(
+            replay.dispatch(&none, "default", "kv", "get", b"a")?,
+            b"a=1"
+        );
+        match replay.dispatch(&none, "default", "kv", "set", b"a") {
+            Err(e @ Error::RecordedError(_)) => assert_eq!(
+                e.to_string(),
+                "No host handler registered for binding=default, namespace=kv, operation=set"
+            ),
+            result => panic!("expected the recorded error, got {:?}", result),
+        }
+        assert!(replayer.remaining().is_empty());
+        replayer.finish()?;
+
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn fails_on_unexpected_calls() {
+        let replayer = Replayer::new(vec![HostCall {
+            binding: "default".to_owned(),
+            namespace: "kv".to_owned(),
+            operation: "get".to_owned(),
+            payload: b"a".to_vec(),
+            response: Response::Ok(b"1".to_vec()),
+        }]);
+        let replay = Recording::Replay(replayer.clone());
+        let none = HostHandlers::new();
+        for (operation, payload) in [("set", &b"a"[..]), ("get", b"b")] {
+            assert!(matches!(
+                replay.dispatch(&none, "default", "kv", operation, payload),
+                Err(Error::UnexpectedHostCall(_))
+            ));
+        }
+        assert_eq!(replayer.remaining().len(), 1);
+        match replayer.finish() {
+            Err(e @ Error::UnusedRecording(1, _)) => assert_eq!(
+                e.to_string(),
+                "1 recorded host calls were never made, starting with binding=default, namespace=kv, operation=get"
+            ),
+            result => panic!("expected the unused call, got {:?}", result),
+        }
+    }
+}
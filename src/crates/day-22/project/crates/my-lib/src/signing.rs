@@ -0,0 +1,181 @@
+use std::{
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub use ed25519_dalek::{Keypair, PublicKey};
+use ed25519_dalek::{SecretKey, Signature, Signer, Verifier};
+
+use crate::{config::TrustPolicy, error::Error};
+
+/// The custom section a module's signature is embedded in.
+pub const SIGNATURE_SECTION: &str = "wapc-signature";
+
+/// The signer's public key followed by the signature.
+const SIGNATURE_LEN: usize = 32 + 64;
+
+/// A signature is appended to the module as a custom section, and covers every
+/// byte before it. Its size is fixed, so the whole section is too: the section
+/// id, its size, the name's length and the name, then the signature.
+fn trailer_header() -> Vec<u8> {
+    let size = 1 + SIGNATURE_SECTION.len() + SIGNATURE_LEN;
+    let mut header = vec![0, size as u8, SIGNATURE_SECTION.len() as u8];
+    header.extend(SIGNATURE_SECTION.as_bytes());
+    header
+}
+
+/// Splits a signed module into the bytes that were signed and the signature.
+fn split(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let header = trailer_header();
+    let start = bytes.len().checked_sub(header.len() + SIGNATURE_LEN)?;
+    let (module, trailer) = bytes.split_at(start);
+    let signature = trailer.strip_prefix(&header[..])?;
+    Some((module, signature))
+}
+
+pub fn is_signed(bytes: &[u8]) -> bool {
+    split(bytes).is_some()
+}
+
+/// Creates a new signing key.
+pub fn generate_key() -> Keypair {
+    Keypair::generate(&mut rand::rngs::OsRng)
+}
+
+/// Signs `wasm` with `key` and embeds the signature in it.
+pub fn sign(wasm: &[u8], key: &Keypair) -> Result<Vec<u8>, Error> {
+    let detached = sign_detached(wasm, key)?;
+    attach(wasm, &detached)
+}
+
+/// Signs `wasm` with `key`, returning the signature to be stored next to the
+/// module in a `.sig` file rather than changing the module.
+pub fn sign_detached(wasm: &[u8], key: &Keypair) -> Result<Vec<u8>, Error> {
+    if is_signed(wasm) {
+        return Err(Error::invalid_module("module is already signed".to_owned()));
+    }
+    let mut detached = key.public.to_bytes().to_vec();
+    detached.extend(key.sign(wasm).to_bytes());
+    Ok(detached)
+}
+
+/// Embeds a detached signature in the module it was made for.
+pub fn attach(wasm: &[u8], detached: &[u8]) -> Result<Vec<u8>, Error> {
+    if detached.len() != SIGNATURE_LEN {
+        return Err(Error::UntrustedModule(format!(
+            "a signature is {} bytes, got {}",
+            SIGNATURE_LEN,
+            detached.len()
+        )));
+    }
+    if is_signed(wasm) {
+        return Err(Error::invalid_module("module is already signed".to_owned()));
+    }
+    let mut signed = wasm.to_vec();
+    signed.extend(trailer_header());
+    signed.extend(detached);
+    Ok(signed)
+}
+
+/// Where the detached signature for the module at `path` is kept.
+pub fn detached_path(path: &Path) -> PathBuf {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    PathBuf::from(sig)
+}
+
+/// Embeds the signature from the `.sig` file next to `path`, if there is one,
+/// in the module read from it.
+pub(crate) fn attach_detached(path: &Path, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let sig_path = detached_path(path);
+    if is_signed(&bytes) || !sig_path.exists() {
+        return Ok(bytes);
+    }
+    let detached =
+        fs::read(&sig_path).map_err(|e| Error::FileNotReadable(sig_path.clone(), e.to_string()))?;
+    attach(&bytes, &detached)
+}
+
+/// Checks that `bytes` carries a valid signature by one of the policy's keys,
+/// and returns that key.
+pub fn verify(bytes: &[u8], policy: &TrustPolicy) -> Result<PublicKey, Error> {
+    let (module, signature) =
+        split(bytes).ok_or_else(|| Error::UntrustedModule("module is not signed".to_owned()))?;
+    let (key, signature) = signature.split_at(32);
+    let key = PublicKey::from_bytes(key).map_err(|e| Error::UntrustedModule(e.to_string()))?;
+    if !policy.trusted_keys.contains(&key) {
+        return Err(Error::UntrustedModule(format!(
+            "signed by {}, which is not a trusted key",
+            hex::encode(key.as_bytes())
+        )));
+    }
+    let signature =
+        Signature::try_from(signature).map_err(|e| Error::UntrustedModule(e.to_string()))?;
+    key.verify(module, &signature).map_err(|_| {
+        Error::UntrustedModule("signature doesn't match the module's contents".to_owned())
+    })?;
+    Ok(key)
+}
+
+/// Reads a hex-encoded public key.
+pub fn parse_public_key(hex: &str) -> Result<PublicKey, Error> {
+    let bytes = hex::decode(hex.trim()).map_err(|e| Error::InvalidKey(e.to_string()))?;
+    PublicKey::from_bytes(&bytes).map_err(|e| Error::InvalidKey(e.to_string()))
+}
+
+/// Reads a hex-encoded secret key, as written by [encode_secret_key].
+pub fn parse_secret_key(hex: &str) -> Result<Keypair, Error> {
+    let bytes = hex::decode(hex.trim()).map_err(|e| Error::InvalidKey(e.to_string()))?;
+    let secret = SecretKey::from_bytes(&bytes).map_err(|e| Error::InvalidKey(e.to_string()))?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+pub fn encode_public_key(key: &PublicKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+pub fn encode_secret_key(key: &Keypair) -> String {
+    hex::encode(key.secret.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn verifies_signed_modules() -> Result<(), Error> {
+        let key = generate_key();
+        let policy = TrustPolicy::default().trust(key.public);
+
+        let signed = sign(WASM, &key)?;
+        assert_eq!(verify(&signed, &policy)?, key.public);
+        let detached = sign_detached(WASM, &key)?;
+        assert_eq!(attach(WASM, &detached)?, signed);
+
+        let keys = parse_secret_key(&encode_secret_key(&key))?;
+        assert_eq!(keys.public, key.public);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unsigned_tampered_and_untrusted_modules() -> Result<(), Error> {
+        let key = generate_key();
+        let policy = TrustPolicy::default().trust(key.public);
+        let untrusted = TrustPolicy::default().trust(generate_key().public);
+
+        let signed = sign(WASM, &key)?;
+        let mut tampered = signed.clone();
+        tampered[5] ^= 1;
+        for (bytes, policy) in [(WASM, &policy), (&tampered, &policy), (&signed, &untrusted)] {
+            assert!(matches!(
+                verify(bytes, policy),
+                Err(Error::UntrustedModule(_))
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, PoisonError, RwLock},
+    thread,
+    time::Duration,
+};
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::Error, permissions, pool::ModulePool, signing, ModuleBuilder};
+
+/// How long to wait for writes to a module to settle before reloading it.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// A module that reloads itself when its wasm file, its detached signature or
+/// its permissions manifest changes on disk.
+///
+/// Calls always run against the last version that loaded successfully. A
+/// reload swaps in the new version for calls made after it finishes, while
+/// calls already running finish against the old one.
+pub struct WatchedModule {
+    shared: Arc<Shared>,
+    _watcher: RecommendedWatcher,
+}
+
+struct Shared {
+    builder: ModuleBuilder,
+    path: PathBuf,
+    signature: PathBuf,
+    manifest: PathBuf,
+    instances: usize,
+    current: RwLock<Arc<ModulePool>>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl WatchedModule {
+    /// Loads the module at `path` into a pool of `instances` and starts watching it.
+    pub fn new<T: AsRef<Path>>(
+        builder: ModuleBuilder,
+        path: T,
+        instances: usize,
+    ) -> Result<Self, Error> {
+        let path = fs::canonicalize(path.as_ref())
+            .map_err(|e| Error::FileNotReadable(path.as_ref().to_path_buf(), e.to_string()))?;
+        let pool = load(&builder, &path, instances)?;
+        let shared = Arc::new(Shared {
+            builder,
+            signature: signing::detached_path(&path),
+            manifest: permissions::manifest_path(&path),
+            path,
+            instances,
+            current: RwLock::new(Arc::new(pool)),
+            last_error: Mutex::new(None),
+        });
+
+        // Build tools often replace a file rather than write to it, so watch
+        // the directory it's in rather than the file itself.
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::watcher(sender, DEBOUNCE)
+            .map_err(|e| Error::WatchFailed(shared.path.clone(), e.to_string()))?;
+        let dir = shared.path.parent().unwrap_or_else(|| Path::new("/"));
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| Error::WatchFailed(shared.path.clone(), e.to_string()))?;
+
+        let watched = shared.clone();
+        thread::spawn(move || {
+            for event in receiver {
+                match event {
+                    DebouncedEvent::Create(path)
+                    | DebouncedEvent::Write(path)
+                    | DebouncedEvent::Rename(_, path)
+                        if path == watched.path
+                            || path == watched.signature
+                            || path == watched.manifest =>
+                    {
+                        // Failures are logged and kept for last_error().
+                        let _ = watched.reload();
+                    }
+                    DebouncedEvent::Error(e, _) => {
+                        warn!("Error watching {}: {}", watched.path.display(), e)
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(WatchedModule {
+            shared,
+            _watcher: watcher,
+        })
+    }
+
+    /// Reloads the module now rather than waiting for its file to change.
+    pub fn reload(&self) -> Result<(), Error> {
+        self.shared.reload()
+    }
+
+    /// Why the most recent reload failed, if it did. The previous version of
+    /// the module keeps serving calls until a reload succeeds.
+    pub fn last_error(&self) -> Option<String> {
+        self.shared
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    pub fn run(&self, operation: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        self.current().run(operation, payload)
+    }
+
+    pub fn invoke<I, O>(&self, operation: &str, input: &I) -> Result<O, Error>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        self.current().invoke(operation, input)
+    }
+
+    /// The version of the module new calls run against.
+    pub fn current(&self) -> Arc<ModulePool> {
+        self.shared
+            .current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Shared {
+    fn reload(&self) -> Result<(), Error> {
+        info!("Reloading {}", self.path.display());
+        let mut last_error = self
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match load(&self.builder, &self.path, self.instances) {
+            Ok(pool) => {
+                *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(pool);
+                *last_error = None;
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "Failed to reload {}, keeping the previous version: {}",
+                    self.path.display(),
+                    e
+                );
+                *last_error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+}
+
+fn load(builder: &ModuleBuilder, path: &Path, instances: usize) -> Result<ModulePool, Error> {
+    let bytes = crate::read(path)?;
+    ModulePool::new(builder.clone().for_file(path)?, &bytes, instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Module;
+
+    #[test]
+    fn keeps_previous_version_when_reload_fails() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("my-lib-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.wasm");
+        fs::copy("./tests/test.wasm", &path).unwrap();
+
+        let module = WatchedModule::new(Module::builder(), &path, 1)?;
+        fs::write(&path, b"not wasm").unwrap();
+        assert!(module.reload().is_err());
+        assert!(module.last_error().is_some());
+
+        let result: String = module.invoke("hello", &"World")?;
+        assert_eq!(result, "Hello, World.");
+
+        fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+
+    /// A guest that responds to every call with `version`.
+    fn versioned(version: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            r#"(module
+                (import "wapc" "__guest_response" (func $respond (param i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "__guest_call") (param i32 i32) (result i32)
+                    (call $respond (i32.const 0) (i32.const {}))
+                    (i32.const 1)))"#,
+            version,
+            version.len()
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn swaps_in_the_new_version_when_the_file_changes() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("my-lib-watch-swap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("versioned.wasm");
+        fs::write(&path, versioned("v1")).unwrap();
+
+        let module = WatchedModule::new(Module::builder(), &path, 1)?;
+        let old = module.current();
+        assert_eq!(module.run("version", b"")?, b"v1");
+        fs::write(&path, versioned("v2")).unwrap();
+
+        // The watcher reloads once writes have settled for DEBOUNCE.
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while Arc::ptr_eq(&old, &module.current()) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "the module never reloaded"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(module.run("version", b"")?, b"v2");
+        assert!(module.last_error().is_none());
+        // Whoever still holds the old version keeps running it.
+        assert_eq!(old.run("version", b"")?, b"v1");
+
+        fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
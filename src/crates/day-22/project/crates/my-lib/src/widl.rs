@@ -0,0 +1,480 @@
+//! A parser for the subset of WIDL that describes a waPC module's interface:
+//! its operations and the types they take and return.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interface {
+    pub namespace: Option<String>,
+    pub operations: Vec<Operation>,
+    pub types: Vec<TypeDefinition>,
+}
+
+impl Interface {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let tokens = tokenize(source)?;
+        Parser { tokens, pos: 0 }.document()
+    }
+
+    pub fn operation(&self, name: &str) -> Option<&Operation> {
+        self.operations.iter().find(|op| op.name == name)
+    }
+
+    pub fn type_definition(&self, name: &str) -> Option<&TypeDefinition> {
+        self.types.iter().find(|ty| ty.name == name)
+    }
+
+    /// Checks `input` against the parameters `operation` declares. Types the
+    /// interface doesn't define are assumed to be valid.
+    pub fn validate(&self, operation: &str, input: &Value) -> Result<(), Error> {
+        let op = self.operation(operation).ok_or_else(|| {
+            Error::InvalidInput(
+                operation.to_owned(),
+                "operation is not declared in the module's interface".to_owned(),
+            )
+        })?;
+        let result = match (op.unary, op.parameters.first()) {
+            (true, Some(parameter)) => self.check(&parameter.ty, input, &parameter.name),
+            _ => self.check_fields(&op.parameters, input, "input"),
+        };
+        result.map_err(|message| Error::InvalidInput(operation.to_owned(), message))
+    }
+
+    fn check_fields(&self, fields: &[Field], value: &Value, path: &str) -> Result<(), String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("{}: expected an object", path))?;
+        for field in fields {
+            let path = format!("{}.{}", path, field.name);
+            match object.get(&field.name) {
+                Some(value) => self.check(&field.ty, value, &path)?,
+                None if matches!(field.ty, TypeRef::Optional(_)) => {}
+                None => return Err(format!("{}: missing", path)),
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, ty: &TypeRef, value: &Value, path: &str) -> Result<(), String> {
+        let valid = match ty {
+            TypeRef::Optional(_) if value.is_null() => true,
+            TypeRef::Optional(inner) => return self.check(inner, value, path),
+            TypeRef::List(item) => match value.as_array() {
+                Some(values) => {
+                    for (i, value) in values.iter().enumerate() {
+                        self.check(item, value, &format!("{}[{}]", path, i))?;
+                    }
+                    true
+                }
+                None => false,
+            },
+            TypeRef::Map(_, item) => match value.as_object() {
+                Some(values) => {
+                    for (key, value) in values {
+                        self.check(item, value, &format!("{}.{}", path, key))?;
+                    }
+                    true
+                }
+                None => false,
+            },
+            TypeRef::Named(name) => match name.as_str() {
+                "string" | "datetime" => value.is_string(),
+                "bool" => value.is_boolean(),
+                "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" => {
+                    value.is_i64() || value.is_u64()
+                }
+                "f32" | "f64" => value.is_number(),
+                "bytes" => value.is_array() || value.is_string(),
+                _ => match self.type_definition(name) {
+                    Some(definition) => return self.check_fields(&definition.fields, value, path),
+                    None => true,
+                },
+            },
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("{}: expected {}", path, ty))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub name: String,
+    pub parameters: Vec<Field>,
+    pub returns: Option<TypeRef>,
+    /// Unary operations, declared as `op{arg: Type}`, take their single
+    /// parameter as the whole input rather than as a field of it.
+    pub unary: bool,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parameters: Vec<String> = self.parameters.iter().map(|p| p.to_string()).collect();
+        if self.unary {
+            write!(f, "{}{{{}}}", self.name, parameters.join(", "))?;
+        } else {
+            write!(f, "{}({})", self.name, parameters.join(", "))?;
+        }
+        match &self.returns {
+            Some(returns) => write!(f, ": {}", returns),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDefinition {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: TypeRef,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.ty)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeRef {
+    Named(String),
+    List(Box<TypeRef>),
+    Map(Box<TypeRef>, Box<TypeRef>),
+    Optional(Box<TypeRef>),
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeRef::Named(name) => write!(f, "{}", name),
+            TypeRef::List(item) => write!(f, "[{}]", item),
+            TypeRef::Map(key, value) => write!(f, "{{{}: {}}}", key, value),
+            TypeRef::Optional(inner) => write!(f, "{}?", inner),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            for c in chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            }
+        } else if c == '"' {
+            chars.next();
+            let block = chars.peek() == Some(&'"');
+            if block {
+                chars.next();
+                if chars.peek() != Some(&'"') {
+                    // An empty string rather than a block description.
+                    tokens.push(Token::Str(String::new()));
+                    continue;
+                }
+                chars.next();
+            }
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') if !block => break,
+                    Some('"') if string.ends_with("\"\"") => {
+                        string.truncate(string.len() - 2);
+                        break;
+                    }
+                    Some('\\') if !block => string.extend(chars.next()),
+                    Some(c) => string.push(c),
+                    None => return Err(Error::InvalidInterface("unterminated string".to_owned())),
+                }
+            }
+            tokens.push(Token::Str(string));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(ident));
+        } else {
+            tokens.push(Token::Punct(c));
+            chars.next();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn document(mut self) -> Result<Interface, Error> {
+        let mut interface = Interface::default();
+        while let Some(token) = self.next() {
+            match token {
+                Token::Ident(keyword) if keyword == "namespace" => {
+                    interface.namespace = Some(self.string()?);
+                    self.annotations()?;
+                }
+                Token::Ident(keyword) if keyword == "interface" => {
+                    self.annotations()?;
+                    self.expect('{')?;
+                    while !self.eat('}') {
+                        interface.operations.push(self.operation()?);
+                    }
+                }
+                Token::Ident(keyword) if keyword == "type" => {
+                    let name = self.ident()?;
+                    self.annotations()?;
+                    self.expect('{')?;
+                    let fields = self.fields('}')?;
+                    interface.types.push(TypeDefinition { name, fields });
+                }
+                Token::Ident(keyword) if keyword == "enum" => {
+                    self.ident()?;
+                    self.annotations()?;
+                    self.skip_block()?;
+                }
+                Token::Ident(keyword) if keyword == "union" => {
+                    self.ident()?;
+                    self.annotations()?;
+                    self.expect('=')?;
+                    self.ident()?;
+                    while self.eat('|') {
+                        self.ident()?;
+                    }
+                }
+                Token::Str(_) => {}
+                Token::Punct('@') => {
+                    self.pos -= 1;
+                    self.annotations()?;
+                }
+                token => return Err(unexpected(Some(token))),
+            }
+        }
+        Ok(interface)
+    }
+
+    fn operation(&mut self) -> Result<Operation, Error> {
+        self.description();
+        self.annotations()?;
+        let name = self.ident()?;
+        let unary = !self.eat('(');
+        let parameters = if unary {
+            self.expect('{')?;
+            self.fields('}')?
+        } else {
+            self.fields(')')?
+        };
+        let returns = if self.eat(':') {
+            Some(self.type_ref()?)
+        } else {
+            None
+        };
+        self.annotations()?;
+        Ok(Operation {
+            name,
+            parameters,
+            returns,
+            unary,
+        })
+    }
+
+    fn fields(&mut self, close: char) -> Result<Vec<Field>, Error> {
+        let mut fields = Vec::new();
+        while !self.eat(close) {
+            self.description();
+            let name = self.ident()?;
+            self.expect(':')?;
+            let ty = self.type_ref()?;
+            if self.eat('=') {
+                // Default values aren't needed to describe the interface.
+                self.next();
+            }
+            self.annotations()?;
+            self.eat(',');
+            fields.push(Field { name, ty });
+        }
+        Ok(fields)
+    }
+
+    fn type_ref(&mut self) -> Result<TypeRef, Error> {
+        let ty = if self.eat('[') {
+            let item = self.type_ref()?;
+            self.expect(']')?;
+            TypeRef::List(Box::new(item))
+        } else if self.eat('{') {
+            let key = self.type_ref()?;
+            self.expect(':')?;
+            let value = self.type_ref()?;
+            self.expect('}')?;
+            TypeRef::Map(Box::new(key), Box::new(value))
+        } else {
+            TypeRef::Named(self.ident()?)
+        };
+        if self.eat('?') {
+            Ok(TypeRef::Optional(Box::new(ty)))
+        } else {
+            Ok(ty)
+        }
+    }
+
+    fn annotations(&mut self) -> Result<(), Error> {
+        while self.eat('@') {
+            self.ident()?;
+            if self.peek() == Some(&Token::Punct('(')) {
+                self.skip_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn description(&mut self) {
+        if let Some(Token::Str(_)) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips a bracketed block along with everything nested in it.
+    fn skip_block(&mut self) -> Result<(), Error> {
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some(Token::Punct('{' | '(' | '[')) => depth += 1,
+                Some(Token::Punct('}' | ')' | ']')) => depth -= 1,
+                Some(_) => {}
+                None => return Err(unexpected(None)),
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, Error> {
+        match self.next() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        match self.next() {
+            Some(Token::Str(string)) => Ok(string),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), Error> {
+        match self.next() {
+            Some(Token::Punct(c)) if c == punct => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+}
+
+fn unexpected(token: Option<Token>) -> Error {
+    match token {
+        Some(token) => Error::InvalidInterface(format!("unexpected {:?}", token)),
+        None => Error::InvalidInterface("unexpected end of input".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SCHEMA: &str = r#"
+        interface {
+          render(blog:Blog, template: string): string
+        }
+
+        type Blog {
+          title: string,
+          body: string,
+          author: string
+        }
+    "#;
+
+    #[test]
+    fn parses_guest_schema() -> Result<(), Error> {
+        let interface = Interface::parse(SCHEMA)?;
+
+        let render = interface.operation("render").unwrap();
+        assert_eq!(
+            render.to_string(),
+            "render(blog: Blog, template: string): string"
+        );
+        let blog = interface.type_definition("Blog").unwrap();
+        let fields: Vec<&str> = blog.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(fields, ["title", "body", "author"]);
+        Ok(())
+    }
+
+    #[test]
+    fn validates_input_against_declared_types() -> Result<(), Error> {
+        let interface = Interface::parse(SCHEMA)?;
+        let blog = json!({ "title": "Tom Sawyer", "body": "TOM!", "author": "Mark Twain" });
+
+        interface.validate(
+            "render",
+            &json!({ "blog": blog, "template": "{{ title }}" }),
+        )?;
+
+        let result =
+            interface.validate("render", &json!({ "blog": { "title": 1 }, "template": "" }));
+        match result {
+            Err(Error::InvalidInput(_, message)) => {
+                assert_eq!(message, "input.blog.title: expected string")
+            }
+            _ => panic!("expected invalid input, got {:?}", result),
+        }
+        Ok(())
+    }
+}
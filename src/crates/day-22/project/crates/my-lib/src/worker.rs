@@ -0,0 +1,96 @@
+use std::sync::mpsc;
+
+use tracing::Span;
+
+use crate::{error::Error, Module, ModuleBuilder};
+
+/// A call handed to a [Worker], with where to send its result.
+pub(crate) struct Job<R> {
+    operation: String,
+    payload: Vec<u8>,
+    /// The caller's span, so the call's spans nest under it on the worker.
+    span: Span,
+    reply: R,
+}
+
+impl<R: Reply> Job<R> {
+    pub(crate) fn new(operation: &str, payload: &[u8], reply: R) -> Self {
+        Job {
+            operation: operation.to_owned(),
+            payload: payload.to_vec(),
+            span: Span::current(),
+            reply,
+        }
+    }
+}
+
+/// Where a [Worker] sends a job's result.
+pub(crate) trait Reply: Send {
+    /// Whether the caller has stopped waiting, so the job needn't run.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    fn send(self, id: usize, result: Result<Vec<u8>, Error>);
+}
+
+/// Replies with the id of the instance that ran the job, along with its result.
+impl Reply for mpsc::Sender<(usize, Result<Vec<u8>, Error>)> {
+    fn send(self, id: usize, result: Result<Vec<u8>, Error>) {
+        let _ = mpsc::Sender::send(&self, (id, result));
+    }
+}
+
+#[cfg(feature = "async")]
+impl Reply for tokio::sync::oneshot::Sender<Result<Vec<u8>, Error>> {
+    fn is_cancelled(&self) -> bool {
+        self.is_closed()
+    }
+
+    fn send(self, _: usize, result: Result<Vec<u8>, Error>) {
+        let _ = tokio::sync::oneshot::Sender::send(self, result);
+    }
+}
+
+/// A [Module] that runs jobs on the thread it was instantiated on, since a
+/// `WapcHost` can't move between threads.
+pub(crate) struct Worker {
+    id: usize,
+    module: Module,
+}
+
+impl Worker {
+    /// Instantiates the worker's module, telling `ready` whether that worked.
+    pub(crate) fn start(
+        id: usize,
+        builder: ModuleBuilder,
+        bytes: &[u8],
+        ready: impl FnOnce(Result<(), Error>),
+    ) -> Option<Self> {
+        match builder.build(bytes) {
+            Ok(module) => {
+                ready(Ok(()));
+                Some(Worker { id, module })
+            }
+            Err(e) => {
+                ready(Err(e));
+                None
+            }
+        }
+    }
+
+    /// Runs jobs until `next` has no more.
+    pub(crate) fn serve<R: Reply>(&self, mut next: impl FnMut() -> Option<Job<R>>) {
+        while let Some(job) = next() {
+            if job.reply.is_cancelled() {
+                trace!("Skipping cancelled call to {}", job.operation);
+                continue;
+            }
+            trace!("Instance {} running {}", self.id, job.operation);
+            let _entered = job.span.enter();
+            let result = self.module.run(&job.operation, &job.payload);
+            job.reply.send(self.id, result);
+        }
+        debug!("Instance {} shutting down", self.id);
+    }
+}
@@ -1,14 +1,23 @@
 mod generated;
+pub mod log;
 pub use generated::*;
 use handlebars::Handlebars;
 use wapc_guest::prelude::*;
 
+/// The schema this module was generated from, embedded in a custom section so
+/// hosts can discover its operations and validate their inputs.
+#[cfg(target_arch = "wasm32")]
+#[link_section = "wapc-interface"]
+#[used]
+static INTERFACE: [u8; include_bytes!("../schema.widl").len()] = *include_bytes!("../schema.widl");
+
 #[no_mangle]
 pub fn wapc_init() {
     Handlers::register_render(render);
 }
 
 fn render(blog: Blog, template: String) -> HandlerResult<String> {
+    log::debug(&format!("Rendering a {} byte template", template.len()));
     let mut handlebars = Handlebars::new();
     handlebars.register_template_string("blog", template)?;
 
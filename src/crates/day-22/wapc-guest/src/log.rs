@@ -0,0 +1,54 @@
+//! Logs through the host's logger. Hosts built on my-lib serve the `wapc:log`
+//! binding for every module, and show these messages with the module's name
+//! as the log target.
+
+use wapc_guest::prelude::*;
+
+const BINDING: &str = "wapc";
+const NAMESPACE: &str = "log";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+/// Logs `message` at `level`. Hosts that don't serve `wapc:log` drop it.
+pub fn log(level: Level, message: &str) {
+    let _ = host_call(BINDING, NAMESPACE, level.as_str(), message.as_bytes());
+}
+
+pub fn error(message: &str) {
+    log(Level::Error, message)
+}
+
+pub fn warn(message: &str) {
+    log(Level::Warn, message)
+}
+
+pub fn info(message: &str) {
+    log(Level::Info, message)
+}
+
+pub fn debug(message: &str) {
+    log(Level::Debug, message)
+}
+
+pub fn trace(message: &str) {
+    log(Level::Trace, message)
+}